@@ -0,0 +1,2 @@
+ALTER TABLE cryptographic_keys
+    ADD COLUMN algorithm TEXT NOT NULL DEFAULT 'Ed25519';
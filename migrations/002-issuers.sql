@@ -0,0 +1,10 @@
+CREATE TABLE issuers
+(
+    id            INTEGER NOT NULL
+        PRIMARY KEY AUTOINCREMENT
+        UNIQUE,
+    algorithm     TEXT    NOT NULL,
+    private_key   BLOB    NOT NULL,
+    public_key_id INTEGER,
+    FOREIGN KEY (public_key_id) REFERENCES cryptographic_keys (id)
+);
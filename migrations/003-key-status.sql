@@ -0,0 +1,5 @@
+ALTER TABLE cryptographic_keys
+    ADD COLUMN revoked INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE cryptographic_keys
+    ADD COLUMN expires_at TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE schemas
+    ADD COLUMN additional_properties INTEGER NOT NULL DEFAULT 1;
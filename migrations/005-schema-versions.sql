@@ -0,0 +1,22 @@
+ALTER TABLE schemas
+    ADD COLUMN name TEXT;
+
+ALTER TABLE schemas
+    ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE schema_versions
+(
+    schema_id             INTEGER NOT NULL,
+    version               INTEGER NOT NULL,
+    schema                BLOB,
+    additional_properties INTEGER NOT NULL DEFAULT 1,
+    PRIMARY KEY (schema_id, version),
+    FOREIGN KEY (schema_id) REFERENCES schemas (id)
+);
+
+INSERT INTO schema_versions (schema_id, version, schema, additional_properties)
+SELECT id, version, schema, additional_properties
+FROM schemas;
+
+ALTER TABLE credentials
+    ADD COLUMN schema_version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,11 @@
+ALTER TABLE credentials
+    ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE schemas
+    ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE cryptographic_keys
+    ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
+
+ALTER TABLE issuers
+    ADD COLUMN revision INTEGER NOT NULL DEFAULT 1;
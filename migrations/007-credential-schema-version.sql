@@ -0,0 +1,16 @@
+-- The foreign key of credentials (schema_id, schema_version) to schema_versions, SQLite can not add one to a table
+CREATE TRIGGER credentials_schema_version_insert
+    BEFORE INSERT
+    ON credentials
+    WHEN NOT EXISTS(SELECT 1 FROM schema_versions WHERE schema_id = NEW.schema_id AND version = NEW.schema_version)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+
+CREATE TRIGGER credentials_schema_version_update
+    BEFORE UPDATE OF schema_id, schema_version
+    ON credentials
+    WHEN NOT EXISTS(SELECT 1 FROM schema_versions WHERE schema_id = NEW.schema_id AND version = NEW.schema_version)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
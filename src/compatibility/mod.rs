@@ -0,0 +1,282 @@
+//! Classifies changes between two versions of a schema
+//!
+//! A change is backward compatible when data conforming to the current schema conforms to the proposed one, and
+//! forward compatible the other way around. Keys neither schema lists are left out of the comparison, so adding an
+//! optional key counts as backward compatible even though data for a lax schema may already hold that key.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::datastructures::{pointer_push, Constraints, Schema, SchemaValueType};
+
+/// Overall compatibility of a proposed schema with the current one
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum Compatibility {
+    Full,
+    Backward,
+    Forward,
+    Breaking,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub enum ChangeKind {
+    AddedOptional,
+    AddedRequired,
+    Removed,
+    MadeOptional,
+    MadeRequired,
+    Widened,
+    Narrowed,
+    Changed,
+    ArityChanged,
+    AdditionalPropertiesChanged,
+}
+
+/// One difference between the schemas
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SchemaChange {
+    /// JSON Pointer (RFC 6901) to where in the data the change applies
+    pub path: String,
+    pub change: ChangeKind,
+    pub backward: bool,
+    pub forward: bool,
+}
+
+/// Outcome of checking a proposed schema against the current one and the credentials stored for it
+#[derive(Serialize, Clone, Debug)]
+pub struct CompatibilityReport {
+    pub compatibility: Compatibility,
+    pub changes: Vec<SchemaChange>,
+    pub credentials_checked: usize,
+    /// Credentials that do not conform to the proposed schema
+    pub breaking_credential_ids: Vec<u64>,
+}
+
+/// Every difference between the schemas, in path order
+pub fn changes(current: &Schema, proposed: &Schema) -> Vec<SchemaChange> {
+    let empty = HashMap::new();
+    let mut diff = Diff {
+        current_lax: current.additional_properties.unwrap_or(true),
+        proposed_lax: proposed.additional_properties.unwrap_or(true),
+        changes: vec!(),
+    };
+    if diff.current_lax != diff.proposed_lax {
+        diff.push("", ChangeKind::AdditionalPropertiesChanged, diff.proposed_lax, diff.current_lax);
+    }
+    diff.map_diff(current.schema.as_ref().unwrap_or(&empty), proposed.schema.as_ref().unwrap_or(&empty), "");
+    diff.changes.sort_by(|a, b| a.path.cmp(&b.path));
+    diff.changes
+}
+
+/// Full when there are no changes or all are both backward and forward compatible
+pub fn classify(changes: &[SchemaChange]) -> Compatibility {
+    match (changes.iter().all(|c| c.backward), changes.iter().all(|c| c.forward)) {
+        (true, true) => Compatibility::Full,
+        (true, false) => Compatibility::Backward,
+        (false, true) => Compatibility::Forward,
+        (false, false) => Compatibility::Breaking,
+    }
+}
+
+struct Diff {
+    current_lax: bool,
+    proposed_lax: bool,
+    changes: Vec<SchemaChange>,
+}
+
+impl Diff {
+    fn push(&mut self, path: &str, change: ChangeKind, backward: bool, forward: bool) {
+        self.changes.push(SchemaChange { path: path.to_owned(), change, backward, forward });
+    }
+
+    fn map_diff(&mut self, current: &HashMap<String, SchemaValueType>, proposed: &HashMap<String, SchemaValueType>, path: &str) {
+        for (k, c) in current {
+            let key_path = pointer_push(path, k);
+            match proposed.get(k) {
+                None => {
+                    let forward = !is_required(c);
+                    self.push(&key_path, ChangeKind::Removed, self.proposed_lax, forward);
+                }
+                Some(p) => {
+                    match (is_required(c), is_required(p)) {
+                        (false, true) => self.push(&key_path, ChangeKind::MadeRequired, false, true),
+                        (true, false) => self.push(&key_path, ChangeKind::MadeOptional, true, false),
+                        _ => {}
+                    }
+                    self.type_diff(strip_required(c), strip_required(p), &key_path);
+                }
+            }
+        }
+        for (k, p) in proposed {
+            if !current.contains_key(k) {
+                if is_required(p) {
+                    self.push(&pointer_push(path, k), ChangeKind::AddedRequired, false, self.current_lax);
+                } else {
+                    self.push(&pointer_push(path, k), ChangeKind::AddedOptional, true, self.current_lax);
+                }
+            }
+        }
+    }
+
+    fn type_diff(&mut self, current: &SchemaValueType, proposed: &SchemaValueType, path: &str) {
+        match (current, proposed) {
+            (c, p) if c == p => {}
+            (SchemaValueType::Map(ref c), SchemaValueType::Map(ref p)) => self.map_diff(c, p, path),
+            (SchemaValueType::List(ref c), SchemaValueType::List(ref p)) if c.len() != p.len() => {
+                self.push(path, ChangeKind::ArityChanged, false, false);
+            }
+            (SchemaValueType::List(ref c), SchemaValueType::List(ref p)) => {
+                for (n, (c, p)) in c.iter().zip(p).enumerate() {
+                    self.type_diff(c, p, &pointer_push(path, &n.to_string()));
+                }
+            }
+            (SchemaValueType::ListOf(box ref c), SchemaValueType::ListOf(box ref p)) => {
+                self.type_diff(c, p, &pointer_push(path, "-"));
+            }
+            (c, p) => {
+                let backward = subsumes(p, c, self.proposed_lax);
+                let forward = subsumes(c, p, self.current_lax);
+                let change = match (backward, forward) {
+                    (true, _) => ChangeKind::Widened,
+                    (false, true) => ChangeKind::Narrowed,
+                    (false, false) => ChangeKind::Changed,
+                };
+                self.push(path, change, backward, forward);
+            }
+        }
+    }
+}
+
+/// Whether every value of narrow is a value of wide, false when unsure
+fn subsumes(wide: &SchemaValueType, narrow: &SchemaValueType, wide_lax: bool) -> bool {
+    use SchemaValueType::*;
+    match (wide, narrow) {
+        (w, n) if w == n => true,
+        (Required(box ref w), n) => subsumes(w, n, wide_lax),
+        (w, Required(box ref n)) => subsumes(w, n, wide_lax),
+        (w, OneOf(ref ns)) => ns.iter().all(|n| subsumes(w, n, wide_lax)),
+        (w, Nullable(box ref n)) => subsumes(w, &Null, wide_lax) && subsumes(w, n, wide_lax),
+        (OneOf(ref ws), n) => ws.iter().any(|w| subsumes(w, n, wide_lax)),
+        (Nullable(box ref w), n) => *n == Null || subsumes(w, n, wide_lax),
+        (w, Constrained(box ref n)) if subsumes(w, &n.value_type, wide_lax) => true,
+        (Constrained(box ref w), Constrained(box ref n)) => {
+            subsumes(&w.value_type, &n.value_type, wide_lax) && looser(w, n)
+        }
+        (Constrained(_), _) => false,
+        (_, Constrained(_)) => false,
+        (String, Date) | (String, DateTime) | (String, Bytes) | (String, Uri) => true,
+        (List(ref ws), List(ref ns)) => ws.len() == ns.len() && ws.iter().zip(ns).all(|(w, n)| subsumes(w, n, wide_lax)),
+        (ListOf(box ref w), List(ref ns)) => ns.iter().all(|n| subsumes(w, n, wide_lax)),
+        (ListOf(box ref w), ListOf(box ref n)) => subsumes(w, n, wide_lax),
+        (Map(ref ws), Map(ref ns)) => {
+            ws.iter().all(|(k, w)| match ns.get(k) {
+                None => !is_required(w),
+                Some(n) => (!is_required(w) || is_required(n)) && subsumes(strip_required(w), strip_required(n), wide_lax),
+            }) && (wide_lax || ns.keys().all(|k| ws.contains_key(k)))
+        }
+        _ => false,
+    }
+}
+
+/// Whether every constraint of wide is at most as strict as the matching one of narrow
+fn looser(wide: &Constraints, narrow: &Constraints) -> bool {
+    fn bound<T: PartialOrd>(w: Option<T>, n: Option<T>, lower: bool) -> bool {
+        match (w, n) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(w), Some(n)) => if lower { w <= n } else { w >= n },
+        }
+    }
+    bound(wide.minimum.as_ref(), narrow.minimum.as_ref(), true)
+        && bound(wide.maximum.as_ref(), narrow.maximum.as_ref(), false)
+        && bound(wide.min_length, narrow.min_length, true)
+        && bound(wide.max_length, narrow.max_length, false)
+        && bound(wide.min_items, narrow.min_items, true)
+        && bound(wide.max_items, narrow.max_items, false)
+        && (wide.pattern.is_none() || wide.pattern == narrow.pattern)
+        && match (&wide.allowed, &narrow.allowed) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(w), Some(n)) => n.iter().all(|v| w.contains(v)),
+        }
+}
+
+fn is_required(sbt: &SchemaValueType) -> bool {
+    matches!(sbt, SchemaValueType::Required(_))
+}
+
+fn strip_required(sbt: &SchemaValueType) -> &SchemaValueType {
+    match sbt {
+        SchemaValueType::Required(box ref inner) => strip_required(inner),
+        s => s,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema(raw: &str, additional_properties: bool) -> Schema {
+        Schema {
+            name: None,
+            schema: Some(serde_json::from_str(raw).unwrap()),
+            additional_properties: Some(additional_properties),
+            version: None,
+            id: None,
+        }
+    }
+
+    fn found(current: &Schema, proposed: &Schema) -> Vec<(String, ChangeKind)> {
+        changes(current, proposed).into_iter().map(|c| (c.path, c.change)).collect()
+    }
+
+    #[test]
+    fn classify_changes() {
+        let current = schema(r#"{"a": {"Required": "Int"}, "b": {"Required": "String"}, "c": {"Required": {"List": ["Bool"]}}, "d": "Date"}"#, true);
+
+        assert_eq!(classify(&changes(&current, &current)), Compatibility::Full);
+
+        let added = schema(r#"{"a": {"Required": "Int"}, "b": {"Required": "String"}, "c": {"Required": {"List": ["Bool"]}}, "d": "Date", "e": "Int"}"#, true);
+        assert_eq!(found(&current, &added), vec!(("/e".to_owned(), ChangeKind::AddedOptional)));
+        assert_eq!(classify(&changes(&current, &added)), Compatibility::Full);
+        assert_eq!(classify(&changes(&added, &current)), Compatibility::Full);
+
+        let widened = schema(r#"{"a": {"Required": {"OneOf": ["Int", "Float"]}}, "b": {"Required": "String"}, "c": {"Required": {"List": ["Bool"]}}, "d": "String"}"#, true);
+        assert_eq!(found(&current, &widened), vec!(
+            ("/a".to_owned(), ChangeKind::Widened),
+            ("/d".to_owned(), ChangeKind::Widened),
+        ));
+        assert_eq!(classify(&changes(&current, &widened)), Compatibility::Backward);
+        assert_eq!(classify(&changes(&widened, &current)), Compatibility::Forward);
+
+        let breaking = schema(r#"{"a": {"Required": {"Constrained": {"type": "Int", "minimum": 0}}}, "c": {"Required": {"List": ["Bool", "Bool"]}}, "d": {"Required": "Date"}}"#, false);
+        assert_eq!(found(&current, &breaking), vec!(
+            ("".to_owned(), ChangeKind::AdditionalPropertiesChanged),
+            ("/a".to_owned(), ChangeKind::Narrowed),
+            ("/b".to_owned(), ChangeKind::Removed),
+            ("/c".to_owned(), ChangeKind::ArityChanged),
+            ("/d".to_owned(), ChangeKind::MadeRequired),
+        ));
+        assert_eq!(classify(&changes(&current, &breaking)), Compatibility::Breaking);
+    }
+
+    #[test]
+    fn nested_changes() {
+        let current = schema(r#"{"a": {"ListOf": {"Map": {"b": "Int"}}}}"#, true);
+        let proposed = schema(r#"{"a": {"ListOf": {"Map": {"b": "Float", "c": {"Required": "Bool"}}}}}"#, true);
+        assert_eq!(found(&current, &proposed), vec!(
+            ("/a/-/b".to_owned(), ChangeKind::Changed),
+            ("/a/-/c".to_owned(), ChangeKind::AddedRequired),
+        ));
+        assert_eq!(classify(&changes(&current, &proposed)), Compatibility::Breaking);
+    }
+
+    #[test]
+    fn looser_constraints() {
+        let current = schema(r#"{"a": {"Constrained": {"type": "String", "enum": ["AU"], "max_length": 2}}}"#, true);
+        let proposed = schema(r#"{"a": {"Constrained": {"type": "String", "enum": ["AU", "NZ"]}}}"#, true);
+        assert_eq!(found(&current, &proposed), vec!(("/a".to_owned(), ChangeKind::Widened)));
+        assert_eq!(found(&proposed, &current), vec!(("/a".to_owned(), ChangeKind::Narrowed)));
+    }
+}
@@ -0,0 +1,195 @@
+use std::convert::TryFrom;
+
+use rand_core::{OsRng, RngCore};
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::Sha256;
+
+use super::{SignatureSigner, SignatureVerifier, VerificationError};
+
+/// Smallest RSA modulus accepted, in bits
+const RSA_MIN_BITS: usize = 2048;
+
+/// Ed25519, keys are the raw 32 byte public key, signatures are 64 bytes
+pub struct Ed25519;
+
+impl Ed25519 {
+    fn parse_public_key(public_key: &[u8]) -> Result<ed25519_dalek::VerifyingKey, VerificationError> {
+        <[u8; 32]>::try_from(public_key).ok()
+            .and_then(|k| ed25519_dalek::VerifyingKey::from_bytes(&k).ok())
+            .ok_or(VerificationError::MalformedPublicKey)
+    }
+}
+
+impl SignatureVerifier for Ed25519 {
+    fn check_public_key(&self, public_key: &[u8]) -> Result<(), VerificationError> {
+        Self::parse_public_key(public_key).map(|_| ())
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), VerificationError> {
+        use ed25519_dalek::Verifier;
+        let public_key = Self::parse_public_key(public_key)?;
+        let signature = ed25519_dalek::Signature::from_slice(signature)
+            .map_err(|_| VerificationError::MalformedSignature)?;
+        public_key.verify(message, &signature).map_err(|_| VerificationError::BadSignature)
+    }
+}
+
+/// Private keys are the 32 byte seed
+impl SignatureSigner for Ed25519 {
+    fn generate(&self) -> Vec<u8> {
+        let mut seed = [0u8; 32];
+        OsRng.fill_bytes(&mut seed);
+        seed.to_vec()
+    }
+
+    fn public_key(&self, private_key: &[u8]) -> Option<Vec<u8>> {
+        let seed = <[u8; 32]>::try_from(private_key).ok()?;
+        Some(ed25519_dalek::SigningKey::from_bytes(&seed).verifying_key().to_bytes().to_vec())
+    }
+
+    fn sign(&self, private_key: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+        use ed25519_dalek::Signer;
+        let seed = <[u8; 32]>::try_from(private_key).ok()?;
+        Some(ed25519_dalek::SigningKey::from_bytes(&seed).sign(message).to_bytes().to_vec())
+    }
+}
+
+/// ECDSA over NIST P-256 with SHA-256, keys are SEC1 encoded points,
+/// signatures are either fixed size `r || s` or ASN.1 DER
+pub struct EcdsaP256;
+
+impl EcdsaP256 {
+    fn parse_public_key(public_key: &[u8]) -> Result<p256::ecdsa::VerifyingKey, VerificationError> {
+        p256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+            .map_err(|_| VerificationError::MalformedPublicKey)
+    }
+}
+
+impl SignatureVerifier for EcdsaP256 {
+    fn check_public_key(&self, public_key: &[u8]) -> Result<(), VerificationError> {
+        Self::parse_public_key(public_key).map(|_| ())
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), VerificationError> {
+        use p256::ecdsa::signature::Verifier;
+        let public_key = Self::parse_public_key(public_key)?;
+        let signature = p256::ecdsa::Signature::from_slice(signature)
+            .or_else(|_| p256::ecdsa::Signature::from_der(signature))
+            .map_err(|_| VerificationError::MalformedSignature)?;
+        public_key.verify(message, &signature).map_err(|_| VerificationError::BadSignature)
+    }
+}
+
+/// Private keys are the 32 byte scalar, public keys are compressed points, signatures are `r || s`
+impl SignatureSigner for EcdsaP256 {
+    fn generate(&self) -> Vec<u8> {
+        p256::ecdsa::SigningKey::random(&mut OsRng).to_bytes().to_vec()
+    }
+
+    fn public_key(&self, private_key: &[u8]) -> Option<Vec<u8>> {
+        let key = p256::ecdsa::SigningKey::from_slice(private_key).ok()?;
+        Some(key.verifying_key().to_encoded_point(true).as_bytes().to_vec())
+    }
+
+    fn sign(&self, private_key: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+        use p256::ecdsa::signature::Signer;
+        let key = p256::ecdsa::SigningKey::from_slice(private_key).ok()?;
+        let signature: p256::ecdsa::Signature = key.sign(message);
+        Some(signature.to_bytes().to_vec())
+    }
+}
+
+/// ECDSA over secp256k1 with SHA-256, encoded the same way as `EcdsaP256`,
+/// only low-S signatures are accepted
+pub struct Secp256k1;
+
+impl Secp256k1 {
+    fn parse_public_key(public_key: &[u8]) -> Result<k256::ecdsa::VerifyingKey, VerificationError> {
+        k256::ecdsa::VerifyingKey::from_sec1_bytes(public_key)
+            .map_err(|_| VerificationError::MalformedPublicKey)
+    }
+}
+
+impl SignatureVerifier for Secp256k1 {
+    fn check_public_key(&self, public_key: &[u8]) -> Result<(), VerificationError> {
+        Self::parse_public_key(public_key).map(|_| ())
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), VerificationError> {
+        use k256::ecdsa::signature::Verifier;
+        let public_key = Self::parse_public_key(public_key)?;
+        let signature = k256::ecdsa::Signature::from_slice(signature)
+            .or_else(|_| k256::ecdsa::Signature::from_der(signature))
+            .map_err(|_| VerificationError::MalformedSignature)?;
+        public_key.verify(message, &signature).map_err(|_| VerificationError::BadSignature)
+    }
+}
+
+/// Encoded as `EcdsaP256`, signatures are low-S
+impl SignatureSigner for Secp256k1 {
+    fn generate(&self) -> Vec<u8> {
+        k256::ecdsa::SigningKey::random(&mut OsRng).to_bytes().to_vec()
+    }
+
+    fn public_key(&self, private_key: &[u8]) -> Option<Vec<u8>> {
+        let key = k256::ecdsa::SigningKey::from_slice(private_key).ok()?;
+        Some(key.verifying_key().to_encoded_point(true).as_bytes().to_vec())
+    }
+
+    fn sign(&self, private_key: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+        use k256::ecdsa::signature::Signer;
+        let key = k256::ecdsa::SigningKey::from_slice(private_key).ok()?;
+        let signature: k256::ecdsa::Signature = key.sign(message);
+        Some(signature.to_bytes().to_vec())
+    }
+}
+
+/// RSASSA-PSS with SHA-256, MGF1 with SHA-256 and a 32 byte salt,
+/// keys are DER encoded SubjectPublicKeyInfo or PKCS#1 of at least 2048 bits
+pub struct RsaPss;
+
+impl RsaPss {
+    fn parse_public_key(public_key: &[u8]) -> Result<RsaPublicKey, VerificationError> {
+        use rsa::traits::PublicKeyParts;
+        RsaPublicKey::from_public_key_der(public_key)
+            .or_else(|_| RsaPublicKey::from_pkcs1_der(public_key))
+            .ok()
+            .filter(|k| k.n().bits() >= RSA_MIN_BITS)
+            .ok_or(VerificationError::MalformedPublicKey)
+    }
+}
+
+impl SignatureVerifier for RsaPss {
+    fn check_public_key(&self, public_key: &[u8]) -> Result<(), VerificationError> {
+        Self::parse_public_key(public_key).map(|_| ())
+    }
+
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), VerificationError> {
+        use rsa::signature::Verifier;
+        let public_key = rsa::pss::VerifyingKey::<Sha256>::new(Self::parse_public_key(public_key)?);
+        let signature = rsa::pss::Signature::try_from(signature)
+            .map_err(|_| VerificationError::MalformedSignature)?;
+        public_key.verify(message, &signature).map_err(|_| VerificationError::BadSignature)
+    }
+}
+
+/// Private keys are 2048 bit PKCS#8 DER, public keys are SubjectPublicKeyInfo DER
+impl SignatureSigner for RsaPss {
+    fn generate(&self) -> Vec<u8> {
+        let key = RsaPrivateKey::new(&mut OsRng, RSA_MIN_BITS).unwrap();
+        key.to_pkcs8_der().unwrap().as_bytes().to_vec()
+    }
+
+    fn public_key(&self, private_key: &[u8]) -> Option<Vec<u8>> {
+        let key = RsaPrivateKey::from_pkcs8_der(private_key).ok()?;
+        Some(key.to_public_key().to_public_key_der().ok()?.as_bytes().to_vec())
+    }
+
+    fn sign(&self, private_key: &[u8], message: &[u8]) -> Option<Vec<u8>> {
+        use rsa::signature::{RandomizedSigner, SignatureEncoding};
+        let key = rsa::pss::SigningKey::<Sha256>::new(RsaPrivateKey::from_pkcs8_der(private_key).ok()?);
+        Some(key.sign_with_rng(&mut OsRng, message).to_vec())
+    }
+}
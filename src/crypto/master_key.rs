@@ -0,0 +1,67 @@
+use std::convert::TryFrom;
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::Aead;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rand_core::{OsRng, RngCore};
+
+const NONCE_LEN: usize = 12;
+
+/// Server held key encrypting issuer private keys at rest with AES-256-GCM
+#[derive(Clone)]
+pub struct MasterKey {
+    cipher: Aes256Gcm,
+}
+
+impl MasterKey {
+    /// Parses a base64 encoded 32 byte key
+    pub fn from_base64(encoded: &str) -> Option<Self> {
+        let key = BASE64.decode(encoded).ok()?;
+        Some(MasterKey { cipher: Aes256Gcm::new_from_slice(&key).ok()? })
+    }
+
+    /// Encrypts with a fresh random nonce, returns nonce || ciphertext
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(self.cipher.encrypt(&Nonce::from(nonce), plaintext).unwrap());
+        sealed
+    }
+
+    /// Reverses `seal`, None when the data was not sealed by this key or was tampered with
+    pub fn open(&self, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = <[u8; NONCE_LEN]>::try_from(nonce).ok()?;
+        self.cipher.decrypt(&Nonce::from(nonce), ciphertext).ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let key = MasterKey::from_base64(&BASE64.encode([1u8; 32])).unwrap();
+        let sealed = key.seal(b"private key");
+        assert_ne!(&sealed[NONCE_LEN..], b"private key");
+        assert_eq!(key.open(&sealed).unwrap(), b"private key");
+
+        let other = MasterKey::from_base64(&BASE64.encode([2u8; 32])).unwrap();
+        assert!(other.open(&sealed).is_none());
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN] ^= 1;
+        assert!(key.open(&tampered).is_none());
+    }
+
+    #[test]
+    fn reject_bad_key() {
+        assert!(MasterKey::from_base64("something").is_none());
+        assert!(MasterKey::from_base64(&BASE64.encode([1u8; 16])).is_none());
+    }
+}
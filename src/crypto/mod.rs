@@ -0,0 +1,262 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde_json::json;
+
+use crate::datastructures::{Credential, CryptographicKeys, SignatureAlgorithm};
+use crate::jcs::canonicalize;
+
+mod algorithms;
+mod master_key;
+
+pub use master_key::MasterKey;
+
+/// Reasons for a credential's finger_print to be rejected
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerificationError {
+    MissingSignature,
+    MissingPublicKey,
+    MalformedSignature,
+    MalformedPublicKey,
+    BadSignature,
+}
+
+impl VerificationError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            VerificationError::MissingSignature => "Missing finger_print field",
+            VerificationError::MissingPublicKey => "Key has no public_key",
+            VerificationError::MalformedSignature => "finger_print is not a base64 encoded signature of the key's algorithm",
+            VerificationError::MalformedPublicKey => "public_key is not a base64 encoded key of the declared algorithm",
+            VerificationError::BadSignature => "finger_print does not match credential data",
+        }
+    }
+}
+
+/// A signature scheme keys can declare, keys and signatures are passed base64 decoded
+pub trait SignatureVerifier: Sync {
+    /// Checks the key material parses as a public key of this algorithm
+    fn check_public_key(&self, public_key: &[u8]) -> Result<(), VerificationError>;
+    /// Checks signature is a valid signature of message by public_key
+    fn verify(&self, public_key: &[u8], message: &[u8], signature: &[u8]) -> Result<(), VerificationError>;
+}
+
+/// Picks the verifier for an algorithm
+pub fn verifier_for(algorithm: SignatureAlgorithm) -> &'static dyn SignatureVerifier {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => &algorithms::Ed25519,
+        SignatureAlgorithm::EcdsaP256 => &algorithms::EcdsaP256,
+        SignatureAlgorithm::Secp256k1 => &algorithms::Secp256k1,
+        SignatureAlgorithm::RsaPss => &algorithms::RsaPss,
+    }
+}
+
+/// Server side key handling for algorithms issuers sign with, keys and signatures are raw bytes,
+/// public keys are encoded as the algorithm's `SignatureVerifier` expects them
+pub trait SignatureSigner: Sync {
+    /// Generates a new private key
+    fn generate(&self) -> Vec<u8>;
+    /// The public key matching the private key, None if the private key is malformed
+    fn public_key(&self, private_key: &[u8]) -> Option<Vec<u8>>;
+    /// Signs message with the private key, None if the private key is malformed
+    fn sign(&self, private_key: &[u8], message: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// Picks the signer for an algorithm
+pub fn signer_for(algorithm: SignatureAlgorithm) -> &'static dyn SignatureSigner {
+    match algorithm {
+        SignatureAlgorithm::Ed25519 => &algorithms::Ed25519,
+        SignatureAlgorithm::EcdsaP256 => &algorithms::EcdsaP256,
+        SignatureAlgorithm::Secp256k1 => &algorithms::Secp256k1,
+        SignatureAlgorithm::RsaPss => &algorithms::RsaPss,
+    }
+}
+
+fn decode_public_key(key: &CryptographicKeys) -> Result<Vec<u8>, VerificationError> {
+    let public_key = key.public_key.as_ref().ok_or(VerificationError::MissingPublicKey)?;
+    BASE64.decode(public_key).map_err(|_| VerificationError::MalformedPublicKey)
+}
+
+/// Checks the key's public_key is a key of its declared algorithm, keys without one are Ed25519
+pub fn check_public_key(key: &CryptographicKeys) -> Result<(), VerificationError> {
+    verifier_for(key.algorithm.unwrap_or_default()).check_public_key(&decode_public_key(key)?)
+}
+
+/// What a credential's finger_print signs over, the JCS canonical form of
+/// an object holding the credential's data, public_key_id, schema_id and schema_version
+pub fn signing_input(cred: &Credential) -> String {
+    canonicalize(&json!({
+        "data": cred.data,
+        "public_key_id": cred.public_key_id,
+        "schema_id": cred.schema_id,
+        "schema_version": cred.schema_version,
+    }))
+}
+
+/// Checks the credential's finger_print is a valid signature of `signing_input` by the key,
+/// using the key's algorithm. Both the key and the signature are base64 encoded
+pub fn verify(cred: &Credential, key: &CryptographicKeys) -> Result<(), VerificationError> {
+    let public_key = decode_public_key(key)?;
+    let finger_print = cred.finger_print.as_ref().ok_or(VerificationError::MissingSignature)?;
+    let signature = BASE64.decode(finger_print).map_err(|_| VerificationError::MalformedSignature)?;
+
+    verifier_for(key.algorithm.unwrap_or_default()).verify(&public_key, signing_input(cred).as_bytes(), &signature)
+}
+
+#[cfg(test)]
+mod test {
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+
+    const RSA_PUBLIC_KEY: &str = "MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAqHoNP96CP0Imn9qeBGXLQNMGWe607nueJDWODjJgUUjP7C8VCdqBjs4p4qkA1Ij1Vn33dt+MSlhbwIHDZ7kDieuYTRi8N2JvJ9FiP4XWA3pOd10kEPCMGmvxgJhC5g5Hxvxu3yKsmJZuIYVdLg7f0KgzVZrBNXtUav4cEJZsSi8LB/5Dzi6D84dR0+0fA25C2w4t3jscdxdeBsuyitl7ZKc3v3aaZjR1i7iAkTh8FMybw9rKIDkQV/xorfUv9fVwSgifPxzLs/gzH12n1W9xIKDeofJdtJXEXVbteJL0IJcKAeBtKHa0yyPfwREtoXvydaqp1asf/+mZc32WT2YTLwIDAQAB";
+    const RSA_PRIVATE_KEY: &str = "MIIEvQIBADANBgkqhkiG9w0BAQEFAASCBKcwggSjAgEAAoIBAQDkJTVPiy57H9Y/2fJiJI0JZwPAyr9ZPWlbe94P7LQnUcQoJP+DZqjcGxwlOyewIHd5+CugbWs2u+PQkGaPRr3AkfN2erCTzjWB/nTlfnKjOcm8s8aPHrsmxHGcRv/SlJ2BUNjq7rVcVqdQS5/Pin4xcAP2nkF8ZIMAVIv+1+7tewNv4s3tl/zmfAK1CrEGLQHCfKGyywXX58m0Y3UunqYHogdK0fYwMHx4hj9+xiG08e/yVOl8tn8k34xdt5OgNWNSyzC5dZhJsVstovSOQN18XWBl/PE654ptUstHPBR8rJaQ3l0aKKeRoRD1BrIRBAzg3mP39DtjRkCHJRcBDZI5AgMBAAECggEALuJmnj4gAzV7tgt4ACs6wJ/m6YvA3vn72M0PyX3/V8U55nYT/FDwiqsXkZ8ifNNvOiWB8aoKymi6XHYQXYlQHW7g9YwpFXbfECPWqgLeVCTgDGN1fpv2IfVk05TTpdbct2KB8WW055D3OU0RPnlbjZVTey7yjjmpoju9uVVuyGaH4Egyl84pT099cJcAWjaKLrFu9bkowP1vIoCo1HjxZo09n8t44a5yIHA9WZkXikFREb13CriJe7KFSgJ8ymQC/KMcdyqfoPzvGcIk2wex7ZDgOoNNLMc0tv0FpMEWfFd9rfBHemtk9W+fjczaU4ExHMofdiBdJgkD/RTKyYXIwQKBgQD5teUKoxzc89En9/okj+NHQX7OvVc6aTS/QDn/YpyvAFxJXcykzBx4J7UT/4osus7jwbJcd1d/vhUKew8qpYij+UKqHBlqwwKzE5elfIr0SRdPdNnY1/uLX0vFHpVZT+1GzqG4FH81aNS40AsuIOMcyc2l5eaJNJ6AB/OyQvbNzQKBgQDp5EOBcnWhGTNRWNiSN6ib6mEXLPQW96rnLRNsbGbS7DsYFIlfb1f+QRwHCu9Lg5YsSx86hcwZjVbYBQdSJyNoR9qemPevs01o8aXBoJB8JXVmaeKMyj4BrJjq59Hui5kHbNDN4EaKPumZDH4PX8C+3BQ8M/eVn+blaJi5PwdKHQKBgAVMzgq2wZMS/bp6ce26vBk0E6vxqttLhloU0+ZOwoKYYF0SR5ir2GLYfOhqwRUH9gIxh0adJddJQosRZ5YBI3BPhxGzPPreHr2MSeiyRwS+VGdUVJdcpjMkF6icaJEXuDKLijUtAjIxAmrVx5b4Dbn7soi143nACykuggajkzd1AoGBAJSbW+jbeLGq+VguQyxkANVPzfCTt2MAaa4IjdbN6WcKgWGDauUgMaBybgRrhi9MpBXwhR8B8/QoQY1vZH9mLrELNnSebIFOQ1jycIbeJe87PaSckj0yIsOdjmtyb4hJWinFkcW9QhSEdJexwEa0aBrcal72BdEjj+ki1CafTVphAoGAehIHvUeQ3TvYX2BvUwZDbSXla6Pu7/VzH3fY2Fk/A6cKk4Nbe3O+c3qR/u2Zdyx+QkHMrTDEhIITNOeP88tMBtK4pew+q/751kdtqONRwun7GEjrPmR8l1b0SsSHFwNRh+HVKYsreR7It4Ip2uaFXVi86DRXanTaXwH24ibPeKs=";
+    const RSA_SIGNATURE: &str = "StEsjFv8KUXeqMRsl1YXRYnMNHOAd8m6YHs1qvfPHaYHUPtVaLZQNGwZibdjhsBSqFojMkYA3lmSyHaisvJi72tA+HmthHgSQZClX4GJbKU7K93GkXMrGxVR+NPpSj7YkwvjM3oOKbntbgtdZJ2x/2AGTd1avE+thrARdsTC7mbfk1YENL6RkI0DqVjL4yVa8sZCGZXNJAo0PJ9jbXZPZF1F2LInlOBvKgZF9tdj8LLABi7RcFwQxBj3Vahy4OZTSGk2XHl006wqqF45++MS4gRbFV/sqVF/9lR40vc9/KpTTuddXiMHAhX3Mh6ZfWH5AgJ6NyJReWCK4ipGc6O5hw==";
+
+    fn key_pair() -> (SigningKey, CryptographicKeys) {
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let key = CryptographicKeys {
+            public_key: Some(BASE64.encode(signing_key.verifying_key().as_bytes())),
+            algorithm: None,
+            revoked: None,
+            expires_at: None,
+            id: Some(1),
+        };
+        (signing_key, key)
+    }
+
+    fn credential() -> Credential {
+        Credential {
+            id: None,
+            schema_id: Some(3),
+            schema_version: Some(2),
+            public_key_id: Some(1),
+            finger_print: None,
+            data: Some(json!({"a": true, "b": [1, 2]})),
+        }
+    }
+
+    #[test]
+    fn verify_valid_signature() {
+        let (signing_key, key) = key_pair();
+        let mut cred = credential();
+        cred.finger_print = Some(BASE64.encode(signing_key.sign(signing_input(&cred).as_bytes()).to_bytes()));
+        assert_eq!(verify(&cred, &key), Ok(()));
+    }
+
+    #[test]
+    fn signing_input_is_canonical() {
+        let mut cred = credential();
+        cred.data = Some(serde_json::from_str("{\"b\": [1.0, 2], \"a\": true}").unwrap());
+        assert_eq!(signing_input(&cred), "{\"data\":{\"a\":true,\"b\":[1,2]},\"public_key_id\":1,\"schema_id\":3,\"schema_version\":2}");
+    }
+
+    #[test]
+    fn verify_tampered_data() {
+        let (signing_key, key) = key_pair();
+        let mut cred = credential();
+        cred.finger_print = Some(BASE64.encode(signing_key.sign(signing_input(&cred).as_bytes()).to_bytes()));
+        cred.data = Some(json!({"a": false, "b": [1, 2]}));
+        assert_eq!(verify(&cred, &key), Err(VerificationError::BadSignature));
+        cred.data = Some(json!({"a": true, "b": [1, 2]}));
+        cred.schema_id = Some(4);
+        assert_eq!(verify(&cred, &key), Err(VerificationError::BadSignature));
+        cred.schema_id = Some(3);
+        cred.schema_version = Some(1);
+        assert_eq!(verify(&cred, &key), Err(VerificationError::BadSignature));
+        cred.schema_version = Some(2);
+        cred.public_key_id = Some(2);
+        assert_eq!(verify(&cred, &key), Err(VerificationError::BadSignature));
+    }
+
+    #[test]
+    fn verify_malformed_input() {
+        let (_, key) = key_pair();
+        let mut cred = credential();
+        assert_eq!(verify(&cred, &key), Err(VerificationError::MissingSignature));
+        cred.finger_print = Some("something".to_owned());
+        assert_eq!(verify(&cred, &key), Err(VerificationError::MalformedSignature));
+        let bad_key = CryptographicKeys { public_key: Some("something".to_owned()), algorithm: None, revoked: None, expires_at: None, id: Some(1) };
+        assert_eq!(verify(&cred, &bad_key), Err(VerificationError::MalformedPublicKey));
+    }
+
+    #[test]
+    fn verify_ecdsa() {
+        use p256::ecdsa::signature::Signer;
+        let mut cred = credential();
+
+        let p256_key = p256::ecdsa::SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let key = CryptographicKeys {
+            public_key: Some(BASE64.encode(p256_key.verifying_key().to_encoded_point(true).as_bytes())),
+            algorithm: Some(SignatureAlgorithm::EcdsaP256),
+            revoked: None,
+            expires_at: None,
+            id: Some(1),
+        };
+        let signature: p256::ecdsa::Signature = p256_key.sign(signing_input(&cred).as_bytes());
+        cred.finger_print = Some(BASE64.encode(signature.to_bytes()));
+        assert_eq!(verify(&cred, &key), Ok(()));
+        cred.finger_print = Some(BASE64.encode(signature.to_der().as_bytes()));
+        assert_eq!(verify(&cred, &key), Ok(()));
+
+        let k256_key = k256::ecdsa::SigningKey::from_slice(&[7u8; 32]).unwrap();
+        let key = CryptographicKeys {
+            public_key: Some(BASE64.encode(k256_key.verifying_key().to_encoded_point(false).as_bytes())),
+            algorithm: Some(SignatureAlgorithm::Secp256k1),
+            revoked: None,
+            expires_at: None,
+            id: Some(1),
+        };
+        assert_eq!(verify(&cred, &key), Err(VerificationError::BadSignature));
+        let signature: k256::ecdsa::Signature = k256_key.sign(signing_input(&cred).as_bytes());
+        cred.finger_print = Some(BASE64.encode(signature.to_bytes()));
+        assert_eq!(verify(&cred, &key), Ok(()));
+    }
+
+    #[test]
+    fn verify_rsa_pss() {
+        let mut cred = credential();
+        cred.schema_id = Some(1);
+        cred.data = Some(json!({"a": true}));
+        cred.finger_print = Some(RSA_SIGNATURE.to_owned());
+        let key = CryptographicKeys {
+            public_key: Some(RSA_PUBLIC_KEY.to_owned()),
+            algorithm: Some(SignatureAlgorithm::RsaPss),
+            revoked: None,
+            expires_at: None,
+            id: Some(1),
+        };
+        assert_eq!(verify(&cred, &key), Ok(()));
+        cred.data = Some(json!({"a": false}));
+        assert_eq!(verify(&cred, &key), Err(VerificationError::BadSignature));
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let rsa_private_key = BASE64.decode(RSA_PRIVATE_KEY).unwrap();
+        for algorithm in [SignatureAlgorithm::Ed25519, SignatureAlgorithm::EcdsaP256, SignatureAlgorithm::Secp256k1, SignatureAlgorithm::RsaPss] {
+            let signer = signer_for(algorithm);
+            // RSA key generation is too slow for a debug build test
+            let private_key = if algorithm == SignatureAlgorithm::RsaPss { rsa_private_key.clone() } else { signer.generate() };
+            let public_key = signer.public_key(&private_key).unwrap();
+            assert_eq!(verifier_for(algorithm).check_public_key(&public_key), Ok(()));
+
+            let signature = signer.sign(&private_key, b"message").unwrap();
+            assert_eq!(verifier_for(algorithm).verify(&public_key, b"message", &signature), Ok(()));
+            assert_eq!(verifier_for(algorithm).verify(&public_key, b"massage", &signature), Err(VerificationError::BadSignature));
+            assert!(signer.sign(b"something", b"message").is_none());
+        }
+    }
+
+    #[test]
+    fn check_key_matches_algorithm() {
+        let (_, mut key) = key_pair();
+        assert_eq!(check_public_key(&key), Ok(()));
+        for algorithm in [SignatureAlgorithm::EcdsaP256, SignatureAlgorithm::Secp256k1, SignatureAlgorithm::RsaPss] {
+            key.algorithm = Some(algorithm);
+            assert_eq!(check_public_key(&key), Err(VerificationError::MalformedPublicKey));
+        }
+        key.public_key = Some(RSA_PUBLIC_KEY.to_owned());
+        assert_eq!(check_public_key(&key), Ok(()));
+    }
+}
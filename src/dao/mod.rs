@@ -1,131 +1,226 @@
-use std::collections::HashMap;
 use std::convert::TryFrom;
 
-use rusqlite::{Connection, Error, NO_PARAMS, Result as SR};
-use rusqlite::types::ToSql;
-use serde_json::Value;
+use rusqlite::{Connection, Error, Result as SR, Row, Transaction, TransactionBehavior};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, Type, ValueRef};
+use serde::de::DeserializeOwned;
 
-use crate::datastructures::{Credential, CryptographicKeys, Schema, SchemaValueType};
+use crate::datastructures::{Credential, CredentialFilter, CryptographicKeys, Issuer, KeyFilter, Page, Schema, SignatureAlgorithm, Sort};
 
+impl ToSql for SignatureAlgorithm {
+    fn to_sql(&self) -> SR<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.name()))
+    }
+}
+
+impl FromSql for SignatureAlgorithm {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str().and_then(|s| SignatureAlgorithm::from_name(s).ok_or(FromSqlError::InvalidType))
+    }
+}
+
+/// Ids are u64 in the API but SQLite stores signed 64-bit integers,
+/// an id above i64::MAX can never have been stored so it is reported as not found rather than wrapped
+fn id_to_sql(id: u64) -> SR<i64> {
+    i64::try_from(id).map_err(|_| Error::QueryReturnedNoRows)
+}
+
+/// Reads an id column, a negative value can only have been written outside the API and is an error
+fn id_from_row(row: &Row, idx: usize) -> SR<u64> {
+    let v = row.get::<_, i64>(idx)?;
+    u64::try_from(v).map_err(|_| Error::IntegralValueOutOfRange(idx, v))
+}
+
+fn opt_id_from_row(row: &Row, idx: usize) -> SR<Option<u64>> {
+    match row.get::<_, Option<i64>>(idx)? {
+        Some(_) => id_from_row(row, idx).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Reads a column holding JSON, JSON that does not parse can only have been written outside the API and is an error
+fn json_from_row<T: DeserializeOwned>(row: &Row, idx: usize) -> SR<T> {
+    serde_json::from_str(&row.get::<_, String>(idx)?).map_err(|e| Error::FromSqlConversionFailure(idx, Type::Text, Box::new(e)))
+}
+
+/// LIMIT and OFFSET parameters, a negative LIMIT has no limit
+fn limit_offset(page: Page) -> [i64; 2] {
+    [page.limit.map_or(-1, i64::from), page.offset.map_or(0, i64::from)]
+}
+
+/// The statement of the ascending and descending pair for the order, ORDER BY can not take it as a parameter
+fn sorted(sort: Sort, [ascending, descending]: [&'static str; 2]) -> &'static str {
+    match sort {
+        Sort::Ascending => ascending,
+        Sort::Descending => descending,
+    }
+}
+
+/// Runs f as one unit of work: in an immediate transaction, so that no other connection writes between what f reads
+/// and what it writes, committed when f returns Ok and rolled back otherwise.
+/// Inside another unit of work f joins it, and is committed or rolled back with it
+pub fn unit_of_work<T, E: From<Error>>(conn: &Connection, f: impl FnOnce(&Connection) -> Result<T, E>) -> Result<T, E> {
+    if !conn.is_autocommit() {
+        return f(conn);
+    }
+    let tx = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+    let res = f(&tx)?;
+    tx.commit()?;
+    Ok(res)
+}
+
+/// The revision of the object with the id in table, incremented by every update of it
+pub fn revision(conn: &Connection, table: &'static str, id: u64) -> SR<u64> {
+    let mut stmt = conn.prepare_cached(&format!("SELECT revision FROM {} WHERE id = ?1", table))?;
+    stmt.query_row(&[&id_to_sql(id)?], |row| row.get::<_, i64>(0)).map(|r| r as u64)
+}
+
+/// Operations of more than one statement run as a unit of work
 pub trait ConnectionRestMapping {
     type Target;
 
-    // SQLite only support 64B Signed integer
-    fn get_by_id(_: &Connection, id: u32) -> SR<Option<Self::Target>>;
-    fn get_all(_: &Connection, limit: Option<u32>, offset: Option<u32>) -> SR<Vec<Self::Target>>;
+    fn get_by_id(_: &Connection, id: u64) -> SR<Option<Self::Target>>;
+    fn get_all(_: &Connection, page: Page) -> SR<Vec<Self::Target>>;
     fn update(_: &Connection, data: &Self) -> SR<()>;
-    fn delete_by_id(_: &Connection, id: u32) -> SR<()>;
-    fn create(_: &Connection, data: &Self) -> SR<u32>;
+    fn delete_by_id(_: &Connection, id: u64) -> SR<()>;
+    fn create(_: &Connection, data: &Self) -> SR<u64>;
 }
 
 impl ConnectionRestMapping for Credential {
     type Target = Self;
 
-    fn get_by_id(conn: &Connection, id: u32) -> Result<Option<Self::Target>, Error> {
-        let mut stmt = conn.prepare("SELECT id, schema_id, public_key_id, data, finger_print FROM credentials WHERE id = ?1")?;
-        let mut iter = stmt.query_map(&[id], |row| Ok(Credential {
-            id: Some(row.get::<_, u32>(0).unwrap()),
-            schema_id: Some(row.get::<_, u32>(1).unwrap()),
-            public_key_id: Some(row.get::<_, u32>(2).unwrap()),
-            data: Some(serde_json::from_str::<Value>(&row.get::<_, String>(3)?).unwrap()),
-            finger_print: Some(row.get(4).unwrap()),
+    fn get_by_id(conn: &Connection, id: u64) -> Result<Option<Self::Target>, Error> {
+        let id = id_to_sql(id)?;
+        let mut stmt = conn.prepare_cached("SELECT id, schema_id, public_key_id, data, finger_print, schema_version FROM credentials WHERE id = ?1")?;
+        let mut iter = stmt.query_map([id], |row| Ok(Credential {
+            id: Some(id_from_row(row, 0)?),
+            schema_id: Some(id_from_row(row, 1)?),
+            public_key_id: Some(id_from_row(row, 2)?),
+            data: Some(json_from_row(row, 3)?),
+            finger_print: Some(row.get(4)?),
+            schema_version: Some(row.get(5)?),
         }))?;
         match iter.next() {
             Some(Ok(k)) => Ok(Some(k)),
-            _ => SR::Err(Error::QueryReturnedNoRows)
+            Some(Err(e)) => Err(e),
+            None => SR::Err(Error::QueryReturnedNoRows)
         }
     }
 
-    fn get_all(conn: &Connection, limit: Option<u32>, offset: Option<u32>) -> Result<Vec<Self::Target>, Error> {
-        let mut raw_stmt = "SELECT id, schema_id, public_key_id, data, finger_print FROM credentials ".to_owned();
-        if let Some(i) = limit {
-            raw_stmt.push_str(format!(" LIMIT {}", i).as_str())
-        }
-        if let Some(i) = offset {
-            raw_stmt.push_str(format!(" OFFSET {}", i).as_str())
-        }
-        let mut stmt = conn.prepare(&raw_stmt)?;
-        let iter = stmt.query_map(NO_PARAMS, |row| Ok(Credential {
-            id: Some(row.get::<_, u32>(0).unwrap() as u32),
-            schema_id: Some(row.get::<_, i64>(1).unwrap() as u32),
-            public_key_id: Some(row.get::<_, i64>(2).unwrap() as u32),
-            data: Some(serde_json::from_str::<Value>(&row.get::<_, String>(3)?).unwrap()),
-            finger_print: Some(row.get(4).unwrap()),
-        }))?;
-        let mut res = vec!();
-        for i in iter {
-            res.push(i?)
-        }
-        Ok(res)
+    fn get_all(conn: &Connection, page: Page) -> Result<Vec<Self::Target>, Error> {
+        Self::get_filtered(conn, CredentialFilter::default(), page)
     }
 
     fn update(conn: &Connection, data: &Self) -> Result<(), Error> {
         let mut stmt = conn
-            .prepare("UPDATE credentials SET data = ?, finger_print = ? WHERE id = ?")?;
+            .prepare_cached("UPDATE credentials SET data = ?, finger_print = ?, revision = revision + 1 WHERE id = ?")?;
         let res = stmt.execute(
             &[&serde_json::to_string(&data.data).unwrap() as &dyn ToSql,
                 &data.finger_print as &dyn ToSql,
-                &(&data.id.map(|x| { i64::try_from(x) }).unwrap()).unwrap() as &dyn ToSql
+                &data.id.map(id_to_sql).transpose()? as &dyn ToSql
             ])?;
-        if res <= 0 {
+        if res == 0 {
             return SR::Err(Error::QueryReturnedNoRows);
         }
         Ok(())
     }
 
-    fn delete_by_id(conn: &Connection, id: u32) -> Result<(), Error> {
-        let id = id as i64;
+    fn delete_by_id(conn: &Connection, id: u64) -> Result<(), Error> {
+        let id = id_to_sql(id)?;
         let mut stmt = conn
-            .prepare("DELETE FROM credentials WHERE id = ?1")?;
+            .prepare_cached("DELETE FROM credentials WHERE id = ?1")?;
         let res = stmt.execute(&[&id])?;
-        if res <= 0 {
+        if res == 0 {
             return SR::Err(Error::QueryReturnedNoRows);
         }
         Ok(())
     }
 
-    fn create(conn: &Connection, data: &Self) -> Result<u32, Error> {
+    /// Pinned to the latest version of the schema when schema_version is None
+    fn create(conn: &Connection, data: &Self) -> Result<u64, Error> {
         let mut stmt = conn
-            .prepare("INSERT INTO credentials (data, public_key_id, schema_id, finger_print) VALUES (?, ?, ?, ?)")?;
+            .prepare_cached("INSERT INTO credentials (data, public_key_id, schema_id, finger_print, schema_version) \
+                             VALUES (?1, ?2, ?3, ?4, COALESCE(?5, (SELECT version FROM schemas WHERE id = ?3)))")?;
 
         stmt.execute(
             &[&serde_json::to_string(&data.data).unwrap() as &dyn ToSql,
-                &(data.public_key_id.map(|x| { i64::try_from(x) }).unwrap()).unwrap() as &dyn ToSql,
-                &(data.schema_id.map(|x| { i64::try_from(x) }).unwrap()).unwrap() as &dyn ToSql,
-                &data.finger_print as &dyn ToSql]
+                &data.public_key_id.map(id_to_sql).transpose()? as &dyn ToSql,
+                &data.schema_id.map(id_to_sql).transpose()? as &dyn ToSql,
+                &data.finger_print as &dyn ToSql,
+                &data.schema_version as &dyn ToSql]
         )?;
-        let res = conn.last_insert_rowid() as u32;
+        let res = conn.last_insert_rowid() as u64;
         Ok(res)
     }
 }
 
-impl ConnectionRestMapping for CryptographicKeys {
-    type Target = Self;
-    fn get_by_id(conn: &Connection, id: u32) -> SR<Option<Self>> {
-        let id = id as i64;
-        let mut stmt = conn.prepare("SELECT id, public_key FROM cryptographic_keys WHERE id = ?1")?;
-        let mut iter = stmt.query_map(&[id], |row| Ok(CryptographicKeys {
-            id: Some(row.get::<_, i64>(0).unwrap() as u32),
-            public_key: Some(row.get(1)?),
-        }))?;
-        match iter.next() {
-            Some(Ok(k)) => Ok(Some(k)),
-            _ => SR::Err(Error::QueryReturnedNoRows)
+impl Credential {
+    pub fn get_filtered(conn: &Connection, filter: CredentialFilter, page: Page) -> SR<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(sorted(page.sort, [
+            "SELECT id, schema_id, public_key_id, data, finger_print, schema_version FROM credentials \
+             WHERE (?1 IS NULL OR schema_id = ?1) AND (?2 IS NULL OR public_key_id = ?2) ORDER BY id LIMIT ?3 OFFSET ?4",
+            "SELECT id, schema_id, public_key_id, data, finger_print, schema_version FROM credentials \
+             WHERE (?1 IS NULL OR schema_id = ?1) AND (?2 IS NULL OR public_key_id = ?2) ORDER BY id DESC LIMIT ?3 OFFSET ?4",
+        ]))?;
+        // An id above i64::MAX can not be referenced, filtering on it matches nothing
+        let (Ok(schema_id), Ok(public_key_id)) = (filter.schema_id.map(id_to_sql).transpose(), filter.public_key_id.map(id_to_sql).transpose()) else {
+            return Ok(vec!());
+        };
+        let [limit, offset] = limit_offset(page);
+        let iter = stmt.query_map(
+            &[&schema_id as &dyn ToSql,
+                &public_key_id as &dyn ToSql,
+                &limit as &dyn ToSql,
+                &offset as &dyn ToSql
+            ], |row| Ok(Credential {
+                id: Some(id_from_row(row, 0)?),
+                schema_id: Some(id_from_row(row, 1)?),
+                public_key_id: Some(id_from_row(row, 2)?),
+                data: Some(json_from_row(row, 3)?),
+                finger_print: Some(row.get(4)?),
+                schema_version: Some(row.get(5)?),
+            }))?;
+        let mut res = vec!();
+        for i in iter {
+            res.push(i?)
         }
+        Ok(res)
     }
 
-    fn get_all(conn: &Connection, limit: Option<u32>, offset: Option<u32>) -> SR<Vec<Self>> {
-        let mut raw_stmt = "SELECT id, public_key FROM cryptographic_keys".to_owned();
-        if let Some(i) = limit {
-            raw_stmt.push_str(format!(" LIMIT {}", i).as_str())
-        }
-        if let Some(i) = offset {
-            raw_stmt.push_str(format!(" OFFSET {}", i).as_str())
-        }
-        let mut stmt = conn.prepare(&raw_stmt)?;
-        let iter = stmt.query_map(NO_PARAMS, |row| Ok(CryptographicKeys {
-            id: Some(row.get::<_, i64>(0).unwrap() as u32),
-            public_key: Some(row.get(1)?),
+    /// Ids of the credentials referencing the schema
+    pub fn ids_by_schema_id(conn: &Connection, schema_id: u64) -> SR<Vec<u64>> {
+        let mut stmt = conn.prepare_cached("SELECT id FROM credentials WHERE schema_id = ?1 ORDER BY id")?;
+        let iter = stmt.query_map(&[&id_to_sql(schema_id)?], |row| id_from_row(row, 0))?;
+        iter.collect()
+    }
+
+    /// Ids of the credentials referencing the key
+    pub fn ids_by_public_key_id(conn: &Connection, public_key_id: u64) -> SR<Vec<u64>> {
+        let mut stmt = conn.prepare_cached("SELECT id FROM credentials WHERE public_key_id = ?1 ORDER BY id")?;
+        let iter = stmt.query_map(&[&id_to_sql(public_key_id)?], |row| id_from_row(row, 0))?;
+        iter.collect()
+    }
+
+    pub fn delete_by_schema_id(conn: &Connection, schema_id: u64) -> SR<()> {
+        conn.execute("DELETE FROM credentials WHERE schema_id = ?1", &[&id_to_sql(schema_id)?])?;
+        Ok(())
+    }
+
+    pub fn delete_by_public_key_id(conn: &Connection, public_key_id: u64) -> SR<()> {
+        conn.execute("DELETE FROM credentials WHERE public_key_id = ?1", &[&id_to_sql(public_key_id)?])?;
+        Ok(())
+    }
+
+    /// Every credential of the schema, whatever version it is pinned to
+    pub fn get_by_schema_id(conn: &Connection, schema_id: u64) -> SR<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, schema_id, public_key_id, data, finger_print, schema_version FROM credentials WHERE schema_id = ?1")?;
+        let iter = stmt.query_map(&[&id_to_sql(schema_id)?], |row| Ok(Credential {
+            id: Some(id_from_row(row, 0)?),
+            schema_id: Some(id_from_row(row, 1)?),
+            public_key_id: Some(id_from_row(row, 2)?),
+            data: Some(json_from_row(row, 3)?),
+            finger_print: Some(row.get(4)?),
+            schema_version: Some(row.get(5)?),
         }))?;
         let mut res = vec!();
         for i in iter {
@@ -133,33 +228,101 @@ impl ConnectionRestMapping for CryptographicKeys {
         }
         Ok(res)
     }
+}
 
-    fn update(conn: &Connection, data: &Self) -> SR<()> {
-        let mut stmt = conn
-            .prepare("UPDATE cryptographic_keys SET public_key = ? WHERE id = ?")?;
-        let res = stmt.execute(&[&data.public_key, &data.id.map(|x| x.to_string())])?;
-        if res <= 0 {
-            return SR::Err(Error::QueryReturnedNoRows);
+impl ConnectionRestMapping for CryptographicKeys {
+    type Target = Self;
+    fn get_by_id(conn: &Connection, id: u64) -> SR<Option<Self>> {
+        let id = id_to_sql(id)?;
+        let mut stmt = conn.prepare_cached("SELECT id, public_key, algorithm, revoked, expires_at FROM cryptographic_keys WHERE id = ?1")?;
+        let mut iter = stmt.query_map([id], |row| Ok(CryptographicKeys {
+            id: Some(id_from_row(row, 0)?),
+            public_key: Some(row.get(1)?),
+            algorithm: Some(row.get(2)?),
+            revoked: Some(row.get(3)?),
+            expires_at: row.get(4)?,
+        }))?;
+        match iter.next() {
+            Some(Ok(k)) => Ok(Some(k)),
+            Some(Err(e)) => Err(e),
+            None => SR::Err(Error::QueryReturnedNoRows)
         }
-        Ok(())
     }
 
-    fn delete_by_id(conn: &Connection, id: u32) -> SR<()> {
-        let id = id as i64;
+    fn get_all(conn: &Connection, page: Page) -> SR<Vec<Self>> {
+        Self::get_filtered(conn, KeyFilter::default(), page)
+    }
+
+    fn update(conn: &Connection, data: &Self) -> SR<()> {
         let mut stmt = conn
-            .prepare("DELETE FROM cryptographic_keys WHERE id = ?1")?;
-        let res = stmt.execute(&[&id])?;
-        if res <= 0 {
+            .prepare_cached("UPDATE cryptographic_keys SET public_key = ?, algorithm = ?, revoked = ?, expires_at = ?, revision = revision + 1 WHERE id = ?")?;
+        let res = stmt.execute(
+            &[&data.public_key as &dyn ToSql,
+                &data.algorithm.unwrap_or_default() as &dyn ToSql,
+                &data.is_revoked() as &dyn ToSql,
+                &data.expires_at as &dyn ToSql,
+                &data.id.map(id_to_sql).transpose()? as &dyn ToSql
+            ])?;
+        if res == 0 {
             return SR::Err(Error::QueryReturnedNoRows);
         }
         Ok(())
     }
 
-    fn create(conn: &Connection, data: &Self) -> SR<u32> {
+    /// Issuers using the key are unlinked from it, they store their public key again on their next issuance
+    fn delete_by_id(conn: &Connection, id: u64) -> SR<()> {
+        unit_of_work(conn, |conn| {
+            let id = id_to_sql(id)?;
+            conn.execute("UPDATE issuers SET public_key_id = NULL, revision = revision + 1 WHERE public_key_id = ?1", &[&id])?;
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM cryptographic_keys WHERE id = ?1")?;
+            let res = stmt.execute(&[&id])?;
+            if res == 0 {
+                return SR::Err(Error::QueryReturnedNoRows);
+            }
+            Ok(())
+        })
+    }
+
+    fn create(conn: &Connection, data: &Self) -> SR<u64> {
         let mut stmt = conn
-            .prepare("INSERT INTO cryptographic_keys (public_key) VALUES (?1)")?;
-        stmt.execute(&[&data.public_key])?;
-        let res = conn.last_insert_rowid() as u32;
+            .prepare_cached("INSERT INTO cryptographic_keys (public_key, algorithm, revoked, expires_at) VALUES (?1, ?2, ?3, ?4)")?;
+        stmt.execute(
+            &[&data.public_key as &dyn ToSql,
+                &data.algorithm.unwrap_or_default() as &dyn ToSql,
+                &data.is_revoked() as &dyn ToSql,
+                &data.expires_at as &dyn ToSql]
+        )?;
+        let res = conn.last_insert_rowid() as u64;
+        Ok(res)
+    }
+}
+
+impl CryptographicKeys {
+    pub fn get_filtered(conn: &Connection, filter: KeyFilter, page: Page) -> SR<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(sorted(page.sort, [
+            "SELECT id, public_key, algorithm, revoked, expires_at FROM cryptographic_keys \
+             WHERE (?1 IS NULL OR algorithm = ?1) AND (?2 IS NULL OR revoked = ?2) ORDER BY id LIMIT ?3 OFFSET ?4",
+            "SELECT id, public_key, algorithm, revoked, expires_at FROM cryptographic_keys \
+             WHERE (?1 IS NULL OR algorithm = ?1) AND (?2 IS NULL OR revoked = ?2) ORDER BY id DESC LIMIT ?3 OFFSET ?4",
+        ]))?;
+        let [limit, offset] = limit_offset(page);
+        let iter = stmt.query_map(
+            &[&filter.algorithm as &dyn ToSql,
+                &filter.revoked as &dyn ToSql,
+                &limit as &dyn ToSql,
+                &offset as &dyn ToSql
+            ], |row| Ok(CryptographicKeys {
+                id: Some(id_from_row(row, 0)?),
+                public_key: Some(row.get(1)?),
+                algorithm: Some(row.get(2)?),
+                revoked: Some(row.get(3)?),
+                expires_at: row.get(4)?,
+            }))?;
+        let mut res = vec!();
+        for i in iter {
+            res.push(i?)
+        }
         Ok(res)
     }
 }
@@ -167,66 +330,336 @@ impl ConnectionRestMapping for CryptographicKeys {
 impl ConnectionRestMapping for Schema {
     type Target = Self;
 
-    fn get_by_id(conn: &Connection, id: u32) -> Result<Option<Self>, Error> {
-        let id = id as i64;
-        let mut stmt = conn.prepare("SELECT id, schema FROM schemas WHERE id = ?1")?;
-        let mut iter = stmt.query_map(&[id], |row| Ok(Self {
-            id: Some(row.get::<_, i64>(0).unwrap() as u32),
-            schema: Some(serde_json::from_str::<HashMap<String, SchemaValueType>>(&row.get::<_, String>(1)?).unwrap()),
+    fn get_by_id(conn: &Connection, id: u64) -> Result<Option<Self>, Error> {
+        let id = id_to_sql(id)?;
+        let mut stmt = conn.prepare_cached("SELECT id, schema, additional_properties, name, version FROM schemas WHERE id = ?1")?;
+        let mut iter = stmt.query_map([id], |row| Ok(Self {
+            id: Some(id_from_row(row, 0)?),
+            schema: Some(json_from_row(row, 1)?),
+            additional_properties: Some(row.get(2)?),
+            name: row.get(3)?,
+            version: Some(row.get(4)?),
         }))?;
         match iter.next() {
             Some(Ok(k)) => Ok(Some(k)),
-            _ => SR::Err(Error::QueryReturnedNoRows)
+            Some(Err(e)) => Err(e),
+            None => SR::Err(Error::QueryReturnedNoRows)
         }
     }
 
-    fn get_all(conn: &Connection, limit: Option<u32>, offset: Option<u32>) -> Result<Vec<Self>, Error> {
-        let mut raw_stmt = "SELECT id, schema FROM schemas".to_owned();
-        if let Some(i) = limit {
-            raw_stmt.push_str(format!(" LIMIT {}", i).as_str())
+    fn get_all(conn: &Connection, page: Page) -> Result<Vec<Self>, Error> {
+        let mut stmt = conn.prepare_cached(sorted(page.sort, [
+            "SELECT id, schema, additional_properties, name, version FROM schemas ORDER BY id LIMIT ?1 OFFSET ?2",
+            "SELECT id, schema, additional_properties, name, version FROM schemas ORDER BY id DESC LIMIT ?1 OFFSET ?2",
+        ]))?;
+        let iter = stmt.query_map(limit_offset(page), |row| Ok(Self {
+            id: Some(id_from_row(row, 0)?),
+            schema: Some(json_from_row(row, 1)?),
+            additional_properties: Some(row.get(2)?),
+            name: row.get(3)?,
+            version: Some(row.get(4)?),
+        }))?;
+        let mut res = vec!();
+        for i in iter {
+            res.push(i?)
         }
-        if let Some(i) = offset {
-            raw_stmt.push_str(format!(" OFFSET {}", i).as_str())
+        Ok(res)
+    }
+
+    /// Publishes the next version, published versions never change, the name is kept when data has none
+    fn update(conn: &Connection, data: &Self) -> Result<(), Error> {
+        unit_of_work(conn, |conn| {
+            let id = data.id.map(id_to_sql).transpose()?;
+            let latest: u32 = conn.query_row("SELECT version FROM schemas WHERE id = ?1", &[&id], |row| row.get(0))?;
+            let version = latest + 1;
+            let schema = serde_json::to_string(&data.schema).unwrap();
+            let additional_properties = data.additional_properties.unwrap_or(true);
+            conn.execute(
+                "INSERT INTO schema_versions (schema_id, version, schema, additional_properties) VALUES (?, ?, ?, ?)",
+                &[&id as &dyn ToSql, &version as &dyn ToSql, &schema as &dyn ToSql, &additional_properties as &dyn ToSql])?;
+            let mut stmt = conn
+                .prepare_cached("UPDATE schemas SET schema = ?, additional_properties = ?, name = COALESCE(?, name), version = ?, revision = revision + 1 WHERE id = ?")?;
+            let res = stmt.execute(
+                &[&schema as &dyn ToSql,
+                    &additional_properties as &dyn ToSql,
+                    &data.name as &dyn ToSql,
+                    &version as &dyn ToSql,
+                    &id as &dyn ToSql
+                ])?;
+            if res == 0 {
+                return SR::Err(Error::QueryReturnedNoRows);
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_by_id(conn: &Connection, id: u64) -> Result<(), Error> {
+        unit_of_work(conn, |conn| {
+            let id = id_to_sql(id)?;
+            conn.execute("DELETE FROM schema_versions WHERE schema_id = ?1", &[&id])?;
+            let mut stmt = conn
+                .prepare_cached("DELETE FROM schemas WHERE id = ?1")?;
+            let res = stmt.execute(&[&id])?;
+            if res == 0 {
+                return SR::Err(Error::QueryReturnedNoRows);
+            }
+            Ok(())
+        })
+    }
+
+    /// Creates version 1
+    fn create(conn: &Connection, data: &Self) -> Result<u64, Error> {
+        unit_of_work(conn, |conn| {
+            let schema = serde_json::to_string(&data.schema).unwrap();
+            let additional_properties = data.additional_properties.unwrap_or(true);
+            let mut stmt = conn
+                .prepare_cached("INSERT INTO schemas (schema, additional_properties, name, version) VALUES (?1, ?2, ?3, 1)")?;
+            stmt.execute(
+                &[&schema as &dyn ToSql,
+                    &additional_properties as &dyn ToSql,
+                    &data.name as &dyn ToSql]
+            )?;
+            let res = conn.last_insert_rowid() as u64;
+            conn.execute(
+                "INSERT INTO schema_versions (schema_id, version, schema, additional_properties) VALUES (?1, 1, ?2, ?3)",
+                &[&id_to_sql(res)? as &dyn ToSql, &schema as &dyn ToSql, &additional_properties as &dyn ToSql])?;
+            Ok(res)
+        })
+    }
+}
+
+impl Schema {
+    /// The schema as it was at the version
+    pub fn get_version(conn: &Connection, id: u64, version: u32) -> SR<Option<Self>> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT v.schema_id, v.schema, v.additional_properties, s.name, v.version FROM schema_versions v \
+             JOIN schemas s ON s.id = v.schema_id WHERE v.schema_id = ?1 AND v.version = ?2")?;
+        let mut iter = stmt.query_map(&[&id_to_sql(id)?, &i64::from(version)], |row| Ok(Self {
+            id: Some(id_from_row(row, 0)?),
+            schema: Some(json_from_row(row, 1)?),
+            additional_properties: Some(row.get(2)?),
+            name: row.get(3)?,
+            version: Some(row.get(4)?),
+        }))?;
+        match iter.next() {
+            Some(Ok(k)) => Ok(Some(k)),
+            Some(Err(e)) => Err(e),
+            None => SR::Err(Error::QueryReturnedNoRows)
         }
-        let mut stmt = conn.prepare(&raw_stmt)?;
-        let iter = stmt.query_map(NO_PARAMS, |row| Ok(Self {
-            id: Some(row.get::<_, i64>(0).unwrap() as u32),
-            schema: Some(serde_json::from_str::<HashMap<String, SchemaValueType>>(&row.get::<_, String>(1)?).unwrap()),
+    }
+
+    /// Every version of the schema, oldest first
+    pub fn get_versions(conn: &Connection, id: u64) -> SR<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(
+            "SELECT v.schema_id, v.schema, v.additional_properties, s.name, v.version FROM schema_versions v \
+             JOIN schemas s ON s.id = v.schema_id WHERE v.schema_id = ?1 ORDER BY v.version")?;
+        let iter = stmt.query_map(&[&id_to_sql(id)?], |row| Ok(Self {
+            id: Some(id_from_row(row, 0)?),
+            schema: Some(json_from_row(row, 1)?),
+            additional_properties: Some(row.get(2)?),
+            name: row.get(3)?,
+            version: Some(row.get(4)?),
         }))?;
         let mut res = vec!();
         for i in iter {
             res.push(i?)
         }
+        if res.is_empty() {
+            return SR::Err(Error::QueryReturnedNoRows);
+        }
         Ok(res)
     }
+}
 
-    fn update(conn: &Connection, data: &Self) -> Result<(), Error> {
+
+impl ConnectionRestMapping for Issuer {
+    type Target = Self;
+
+    fn get_by_id(conn: &Connection, id: u64) -> SR<Option<Self>> {
+        let id = id_to_sql(id)?;
+        let mut stmt = conn.prepare_cached("SELECT id, algorithm, public_key_id, private_key FROM issuers WHERE id = ?1")?;
+        let mut iter = stmt.query_map([id], |row| Ok(Issuer {
+            id: Some(id_from_row(row, 0)?),
+            algorithm: Some(row.get(1)?),
+            public_key_id: opt_id_from_row(row, 2)?,
+            private_key: Some(row.get(3)?),
+        }))?;
+        match iter.next() {
+            Some(Ok(k)) => Ok(Some(k)),
+            Some(Err(e)) => Err(e),
+            None => SR::Err(Error::QueryReturnedNoRows)
+        }
+    }
+
+    fn get_all(conn: &Connection, page: Page) -> SR<Vec<Self>> {
+        let mut stmt = conn.prepare_cached(sorted(page.sort, [
+            "SELECT id, algorithm, public_key_id FROM issuers ORDER BY id LIMIT ?1 OFFSET ?2",
+            "SELECT id, algorithm, public_key_id FROM issuers ORDER BY id DESC LIMIT ?1 OFFSET ?2",
+        ]))?;
+        let iter = stmt.query_map(limit_offset(page), |row| Ok(Issuer {
+            id: Some(id_from_row(row, 0)?),
+            algorithm: Some(row.get(1)?),
+            public_key_id: opt_id_from_row(row, 2)?,
+            private_key: None,
+        }))?;
+        let mut res = vec!();
+        for i in iter {
+            res.push(i?)
+        }
+        Ok(res)
+    }
+
+    /// Only the public key reference can change, the private key is fixed at creation
+    fn update(conn: &Connection, data: &Self) -> SR<()> {
         let mut stmt = conn
-            .prepare("UPDATE schemas SET schema = ? WHERE id = ?")?;
-        let res = stmt.execute(&[&serde_json::to_string(&data.schema).unwrap(), &data.id.map(|x| x.to_string()).unwrap()])?;
-        if res <= 0 {
+            .prepare_cached("UPDATE issuers SET public_key_id = ?, revision = revision + 1 WHERE id = ?")?;
+        let res = stmt.execute(&[&data.public_key_id.map(id_to_sql).transpose()?, &data.id.map(id_to_sql).transpose()?])?;
+        if res == 0 {
             return SR::Err(Error::QueryReturnedNoRows);
         }
         Ok(())
     }
 
-    fn delete_by_id(conn: &Connection, id: u32) -> Result<(), Error> {
-        let id = id as i64;
+    fn delete_by_id(conn: &Connection, id: u64) -> SR<()> {
+        let id = id_to_sql(id)?;
         let mut stmt = conn
-            .prepare("DELETE FROM schemas WHERE id = ?1")?;
+            .prepare_cached("DELETE FROM issuers WHERE id = ?1")?;
         let res = stmt.execute(&[&id])?;
-        if res <= 0 {
+        if res == 0 {
             return SR::Err(Error::QueryReturnedNoRows);
         }
         Ok(())
     }
 
-    fn create(conn: &Connection, data: &Self) -> Result<u32, Error> {
+    fn create(conn: &Connection, data: &Self) -> SR<u64> {
         let mut stmt = conn
-            .prepare("INSERT INTO schemas (schema) VALUES (?1)")?;
-        stmt.execute(&[&serde_json::to_string(&data.schema).unwrap()])?;
-        let res = conn.last_insert_rowid() as u32;
+            .prepare_cached("INSERT INTO issuers (algorithm, public_key_id, private_key) VALUES (?1, ?2, ?3)")?;
+        stmt.execute(
+            &[&data.algorithm.unwrap_or_default() as &dyn ToSql,
+                &data.public_key_id.map(id_to_sql).transpose()? as &dyn ToSql,
+                &data.private_key as &dyn ToSql]
+        )?;
+        let res = conn.last_insert_rowid() as u64;
         Ok(res)
     }
 }
 
+#[cfg(test)]
+mod test {
+    use rusqlite::{Connection, NO_PARAMS};
+
+    use super::*;
+
+    fn connection() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::database::migrate(&mut conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn ids_at_the_boundaries() {
+        let conn = connection();
+        let max = i64::MAX as u64;
+        conn.execute("INSERT INTO cryptographic_keys (id, public_key) VALUES (?1, 'k')", &[&i64::MAX]).unwrap();
+        assert_eq!(CryptographicKeys::get_by_id(&conn, max).unwrap().unwrap().id, Some(max));
+        assert_eq!(CryptographicKeys::get_all(&conn, Page::default()).unwrap()[0].id, Some(max));
+        assert!(matches!(CryptographicKeys::get_by_id(&conn, max + 1), Err(Error::QueryReturnedNoRows)));
+        assert!(matches!(CryptographicKeys::delete_by_id(&conn, u64::MAX), Err(Error::QueryReturnedNoRows)));
+
+        conn.execute("INSERT INTO cryptographic_keys (id, public_key) VALUES (-1, 'k')", NO_PARAMS).unwrap();
+        assert!(matches!(CryptographicKeys::get_all(&conn, Page::default()), Err(Error::IntegralValueOutOfRange(0, -1))));
+    }
+
+    #[test]
+    fn unparsable_json_is_an_error() {
+        let conn = connection();
+        conn.execute("INSERT INTO schemas (schema) VALUES ('{\"a\": \"Nothing\"}')", NO_PARAMS).unwrap();
+        conn.execute("INSERT INTO schema_versions (schema_id, version) VALUES (1, 1)", NO_PARAMS).unwrap();
+        assert!(matches!(Schema::get_all(&conn, Page::default()), Err(Error::FromSqlConversionFailure(1, _, _))));
+
+        conn.execute("INSERT INTO cryptographic_keys (public_key) VALUES ('k')", NO_PARAMS).unwrap();
+        conn.execute("INSERT INTO credentials (schema_id, public_key_id, data, finger_print) VALUES (1, 1, 'not json', 'f')",
+                     NO_PARAMS).unwrap();
+        assert!(matches!(Credential::get_all(&conn, Page::default()), Err(Error::FromSqlConversionFailure(3, _, _))));
+    }
+
+    #[test]
+    fn unreadable_rows_are_not_missing() {
+        let conn = connection();
+        conn.execute("INSERT INTO cryptographic_keys (id, public_key, algorithm) VALUES (1, 'k', 'Unknown')", NO_PARAMS).unwrap();
+        assert!(matches!(CryptographicKeys::get_by_id(&conn, 1), Err(Error::InvalidColumnType(..))));
+        assert!(matches!(CryptographicKeys::get_by_id(&conn, 2), Err(Error::QueryReturnedNoRows)));
+    }
+
+    #[test]
+    fn filters_are_bound_as_parameters() {
+        let conn = connection();
+        let schema = serde_json::from_str::<Schema>("{\"schema\": {\"a\": \"Bool\"}}").unwrap();
+        let schema_id = Schema::create(&conn, &schema).unwrap();
+        let key = serde_json::from_str::<CryptographicKeys>("{\"public_key\": \"k\", \"revoked\": true}").unwrap();
+        let public_key_id = CryptographicKeys::create(&conn, &key).unwrap();
+        for _ in 0..3 {
+            conn.execute("INSERT INTO credentials (schema_id, public_key_id, data, finger_print) VALUES (?1, ?2, '{}', 'f')",
+                         &[&id_to_sql(schema_id).unwrap(), &id_to_sql(public_key_id).unwrap()]).unwrap();
+        }
+        let ids = |filter, page| Credential::get_filtered(&conn, filter, page).unwrap().iter().map(|c| c.id.unwrap()).collect::<Vec<_>>();
+        let by_schema = CredentialFilter { schema_id: Some(schema_id), public_key_id: None };
+        assert_eq!(ids(by_schema, Page::default()), [1, 2, 3]);
+        assert_eq!(ids(by_schema, Page { limit: Some(2), offset: Some(1), sort: Sort::Descending }), [2, 1]);
+        assert_eq!(ids(CredentialFilter { schema_id: Some(schema_id), public_key_id: Some(public_key_id + 1) }, Page::default()), [0u64; 0]);
+        assert_eq!(ids(CredentialFilter { schema_id: Some(u64::MAX), public_key_id: None }, Page::default()), [0u64; 0]);
+
+        let keys = |filter| CryptographicKeys::get_filtered(&conn, filter, Page::default()).unwrap().len();
+        assert_eq!(keys(KeyFilter { algorithm: Some(SignatureAlgorithm::Ed25519), revoked: Some(true) }), 1);
+        assert_eq!(keys(KeyFilter { algorithm: None, revoked: Some(false) }), 0);
+        assert_eq!(keys(KeyFilter { algorithm: Some(SignatureAlgorithm::RsaPss), revoked: None }), 0);
+    }
+
+    #[test]
+    fn unit_of_work_is_all_or_nothing() {
+        let conn = connection();
+        let key = serde_json::from_str::<CryptographicKeys>("{\"public_key\": \"k\"}").unwrap();
+        let res = unit_of_work(&conn, |conn| {
+            CryptographicKeys::create(conn, &key)?;
+            // Joins the outer unit of work, so it is rolled back with it
+            unit_of_work(conn, |conn| CryptographicKeys::create(conn, &key))?;
+            CryptographicKeys::delete_by_id(conn, 1000)
+        });
+        assert!(matches!(res, Err(Error::QueryReturnedNoRows)));
+        assert!(conn.is_autocommit());
+        assert!(CryptographicKeys::get_all(&conn, Page::default()).unwrap().is_empty());
+
+        // Deleting a schema credentials reference used to delete its versions before failing
+        conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+        let schema = serde_json::from_str::<Schema>("{\"schema\": {\"a\": \"Bool\"}}").unwrap();
+        let schema_id = Schema::create(&conn, &schema).unwrap();
+        conn.execute("INSERT INTO credentials (schema_id, schema_version) VALUES (?1, 1)", &[&id_to_sql(schema_id).unwrap()]).unwrap();
+        assert!(Schema::delete_by_id(&conn, schema_id).is_err());
+        assert_eq!(Schema::get_versions(&conn, schema_id).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn credentials_reference_a_schema_version() {
+        let conn = connection();
+        let schema = serde_json::from_str::<Schema>("{\"schema\": {\"a\": \"Bool\"}}").unwrap();
+        let schema_id = Schema::create(&conn, &schema).unwrap();
+        Schema::update(&conn, &Schema { id: Some(schema_id), ..schema }).unwrap();
+        let key = serde_json::from_str::<CryptographicKeys>("{\"public_key\": \"k\"}").unwrap();
+        let public_key_id = CryptographicKeys::create(&conn, &key).unwrap();
+        let cred = |schema_version| Credential {
+            id: None,
+            schema_id: Some(schema_id),
+            schema_version,
+            public_key_id: Some(public_key_id),
+            finger_print: Some("f".to_owned()),
+            data: Some(serde_json::json!({"a": true})),
+        };
+        let latest = Credential::create(&conn, &cred(None)).unwrap();
+        assert_eq!(Credential::get_by_id(&conn, latest).unwrap().unwrap().schema_version, Some(2));
+        assert!(Credential::create(&conn, &cred(Some(1))).is_ok());
+        let missing = Credential::create(&conn, &cred(Some(3)));
+        assert!(matches!(missing, Err(Error::SqliteFailure(f, _)) if f.code == rusqlite::ErrorCode::ConstraintViolation));
+        let moved = conn.execute("UPDATE credentials SET schema_version = 3 WHERE id = ?1", &[&id_to_sql(latest).unwrap()]);
+        assert!(moved.is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rocket::config::{Config, ConfigError};
+use rusqlite::{Connection, NO_PARAMS, OpenFlags, Result as SR, ToSql};
+
+/// Statements the dao prepares, with room to spare, so that each is parsed once per connection
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+const JOURNAL_MODES: [&str; 6] = ["DELETE", "TRUNCATE", "PERSIST", "MEMORY", "WAL", "OFF"];
+
+/// Where objects are stored
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackendKind {
+    /// A SQLite file, the default
+    Sqlite,
+    /// An LMDB environment, database_path is its directory
+    Lmdb,
+    /// Nothing survives a restart, database_path is ignored
+    Memory,
+}
+
+impl BackendKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sqlite" => Some(BackendKind::Sqlite),
+            "lmdb" => Some(BackendKind::Lmdb),
+            "memory" => Some(BackendKind::Memory),
+            _ => None,
+        }
+    }
+}
+
+/// Database settings, read from the database_* keys of the Rocket config (Rocket.toml or ROCKET_DATABASE_*)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatabaseConfig {
+    pub backend: BackendKind,
+    pub path: PathBuf,
+    /// Left as the database has it when None, WAL by default so readers do not wait on writers
+    pub journal_mode: Option<String>,
+    pub busy_timeout: Duration,
+    pub pool_size: u32,
+    pub read_only: bool,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            backend: BackendKind::Sqlite,
+            path: PathBuf::from("db.sqlite"),
+            journal_mode: Some("WAL".to_owned()),
+            busy_timeout: Duration::from_millis(5000),
+            pool_size: 4,
+            read_only: false,
+        }
+    }
+}
+
+/// The value of an optional config key, Ok(None) when it is not set
+pub fn optional<T>(value: Result<T, ConfigError>) -> Result<Option<T>, String> {
+    match value {
+        Ok(v) => Ok(Some(v)),
+        Err(ConfigError::Missing(_)) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+impl DatabaseConfig {
+    /// Reads the settings, falling back to the defaults for keys that are not set
+    pub fn from_config(config: &Config) -> Result<Self, String> {
+        let default = Self::default();
+        let backend = match optional(config.get_str("database_backend"))? {
+            Some(name) => BackendKind::from_name(name)
+                .ok_or_else(|| format!("database_backend must be one of sqlite, lmdb, memory, got '{}'", name))?,
+            None => default.backend,
+        };
+        let journal_mode = match optional(config.get_str("database_journal_mode"))? {
+            Some(mode) if JOURNAL_MODES.contains(&mode.to_uppercase().as_str()) => Some(mode.to_uppercase()),
+            Some(mode) => return Err(format!(
+                "database_journal_mode must be one of {}, got '{}'", JOURNAL_MODES.join(", "), mode)),
+            None => default.journal_mode,
+        };
+        let busy_timeout = match optional(config.get_int("database_busy_timeout"))? {
+            Some(ms) if ms >= 0 => Duration::from_millis(ms as u64),
+            Some(ms) => return Err(format!("database_busy_timeout must be a number of milliseconds, got {}", ms)),
+            None => default.busy_timeout,
+        };
+        let pool_size = match optional(config.get_int("database_pool_size"))? {
+            Some(n) if n > 0 && n <= i64::from(u32::MAX) => n as u32,
+            Some(n) => return Err(format!("database_pool_size must be at least 1, got {}", n)),
+            None => default.pool_size,
+        };
+        Ok(DatabaseConfig {
+            backend,
+            path: optional(config.get_str("database_path"))?.map(PathBuf::from).unwrap_or(default.path),
+            journal_mode,
+            busy_timeout,
+            pool_size,
+            read_only: optional(config.get_bool("database_read_only"))?.unwrap_or(default.read_only),
+        })
+    }
+
+    fn flags(&self) -> OpenFlags {
+        let flags = if self.read_only {
+            OpenFlags::SQLITE_OPEN_READ_ONLY
+        } else {
+            OpenFlags::SQLITE_OPEN_READ_WRITE
+        };
+        flags | OpenFlags::SQLITE_OPEN_NO_MUTEX
+    }
+
+    /// The pragmas every connection runs with, foreign keys enforced
+    fn configure(conn: &Connection, busy_timeout: Duration, journal_mode: Option<&str>) -> SR<()> {
+        conn.busy_timeout(busy_timeout)?;
+        conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+        conn.execute_batch("PRAGMA foreign_keys = ON")?;
+        if let Some(mode) = journal_mode {
+            conn.query_row(&format!("PRAGMA journal_mode = {}", mode), NO_PARAMS, |_| Ok(()))?;
+        }
+        Ok(())
+    }
+
+    /// The journal mode to set, None when read only as it can not be changed then
+    fn journal_mode(&self) -> Option<&str> {
+        self.journal_mode.as_deref().filter(|_| !self.read_only)
+    }
+
+    /// Opens a connection with the configured settings, never creates the database file
+    pub fn open(&self) -> SR<Connection> {
+        let conn = Connection::open_with_flags(&self.path, self.flags())?;
+        Self::configure(&conn, self.busy_timeout, self.journal_mode())?;
+        Ok(conn)
+    }
+
+    /// A pool of pool_size connections opened as open does
+    pub fn pool(&self) -> Result<Pool<SqliteConnectionManager>, r2d2::Error> {
+        let (busy_timeout, journal_mode) = (self.busy_timeout, self.journal_mode().map(str::to_owned));
+        let manager = SqliteConnectionManager::file(&self.path)
+            .with_flags(self.flags())
+            .with_init(move |conn| Self::configure(conn, busy_timeout, journal_mode.as_deref()));
+        Pool::builder().max_size(self.pool_size).build(manager)
+    }
+
+    /// Brings the database up to date with the migrations of this binary, returning those applied.
+    /// The database file is only created when create is set, a read only database must already be up to date
+    pub fn migrate(&self, create: bool) -> Result<Vec<&'static Migration>, String> {
+        let path = self.path.display();
+        if !create && !Path::new(&self.path).is_file() {
+            return Err(format!("database {} does not exist, create it with --migrate-only", path));
+        }
+        let mut conn = if create && !self.read_only {
+            Connection::open(&self.path).and_then(|conn| {
+                conn.execute_batch("PRAGMA foreign_keys = ON")?;
+                Ok(conn)
+            })
+        } else {
+            self.open()
+        }.map_err(|e| format!("database {} can not be opened: {}", path, e))?;
+        if self.read_only {
+            return match pending(&conn).map_err(|e| format!("database {} {}", path, e))?.first() {
+                Some(m) => Err(format!("database {} is read only and migration {} is not applied", path, m.name)),
+                None => Ok(vec![]),
+            };
+        }
+        migrate(&mut conn).map_err(|e| format!("database {} {}", path, e))
+    }
+}
+
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Every file of ./migrations, embedded in the binary and applied in order of version
+pub static MIGRATIONS: [Migration; 8] = [
+    Migration { version: 0, name: "000-init", sql: include_str!("../../migrations/000-init.sql") },
+    Migration { version: 1, name: "001-key-algorithm", sql: include_str!("../../migrations/001-key-algorithm.sql") },
+    Migration { version: 2, name: "002-issuers", sql: include_str!("../../migrations/002-issuers.sql") },
+    Migration { version: 3, name: "003-key-status", sql: include_str!("../../migrations/003-key-status.sql") },
+    Migration { version: 4, name: "004-schema-additional-properties", sql: include_str!("../../migrations/004-schema-additional-properties.sql") },
+    Migration { version: 5, name: "005-schema-versions", sql: include_str!("../../migrations/005-schema-versions.sql") },
+    Migration { version: 6, name: "006-revisions", sql: include_str!("../../migrations/006-revisions.sql") },
+    Migration { version: 7, name: "007-credential-schema-version", sql: include_str!("../../migrations/007-credential-schema-version.sql") },
+];
+
+/// What each migration a database may have had applied by hand, before schema_migrations existed, leaves behind:
+/// a table, or a column of a table
+const UNTRACKED_MARKERS: [(&str, Option<&str>); 6] = [
+    ("credentials", None),
+    ("cryptographic_keys", Some("algorithm")),
+    ("issuers", None),
+    ("cryptographic_keys", Some("revoked")),
+    ("schemas", Some("additional_properties")),
+    ("credentials", Some("schema_version")),
+];
+
+fn table_exists(conn: &Connection, table: &str) -> SR<bool> {
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1", &[table],
+                   |row| row.get::<_, i64>(0).map(|n| n > 0))
+}
+
+fn column_exists(conn: &Connection, table: &str, column: &str) -> SR<bool> {
+    conn.query_row("SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2", &[table, column],
+                   |row| row.get::<_, i64>(0).map(|n| n > 0))
+}
+
+/// (version, name) of the migrations applied to the database, oldest first
+fn applied(conn: &Connection) -> Result<Vec<(u32, String)>, String> {
+    let error = |e: rusqlite::Error| format!("can not be read: {}", e);
+    if table_exists(conn, "schema_migrations").map_err(error)? {
+        let mut stmt = conn.prepare("SELECT version, name FROM schema_migrations ORDER BY version").map_err(error)?;
+        let rows = stmt.query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?))).map_err(error)?;
+        return rows.collect::<SR<Vec<_>>>().map_err(error);
+    }
+    // Untracked, applied by hand one migration after the other as far as what they left behind goes
+    let mut untracked = 0;
+    for (table, column) in UNTRACKED_MARKERS.iter() {
+        let applied = match column {
+            None => table_exists(conn, table),
+            Some(column) => column_exists(conn, table, column),
+        }.map_err(error)?;
+        if !applied {
+            break;
+        }
+        untracked += 1;
+    }
+    Ok(MIGRATIONS[..untracked].iter().map(|m| (m.version, m.name.to_owned())).collect())
+}
+
+/// The migrations still to apply, refusing databases migrated further than, or differently from, this binary
+pub fn pending(conn: &Connection) -> Result<Vec<&'static Migration>, String> {
+    let applied = applied(conn)?;
+    let latest = MIGRATIONS.last().map(|m| m.version).unwrap_or_default();
+    for (i, (version, name)) in applied.iter().enumerate() {
+        match MIGRATIONS.get(i) {
+            Some(m) if m.version == *version && m.name == name => (),
+            _ if *version > latest => return Err(format!(
+                "is at migration {}, newer than the latest this binary knows, {}", name, MIGRATIONS[latest as usize].name)),
+            _ => return Err(format!("has migration {} applied which this binary does not have", name)),
+        }
+    }
+    Ok(MIGRATIONS[applied.len()..].iter().collect())
+}
+
+/// Applies the pending migrations, each in its own transaction, recording them in schema_migrations
+pub fn migrate(conn: &mut Connection) -> Result<Vec<&'static Migration>, String> {
+    let applied = applied(conn)?;
+    let pending = pending(conn)?;
+    let error = |name: &str, e: rusqlite::Error| format!("migration {} failed: {}", name, e);
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations
+         (
+             version    INTEGER NOT NULL PRIMARY KEY,
+             name       TEXT    NOT NULL,
+             applied_at TEXT
+         )").map_err(|e| error("schema_migrations", e))?;
+    // Records the untracked migrations of a database migrated by hand, without an applied_at
+    for (version, name) in applied.iter() {
+        conn.execute("INSERT OR IGNORE INTO schema_migrations (version, name) VALUES (?1, ?2)", &[version as &dyn ToSql, name])
+            .map_err(|e| error(name, e))?;
+    }
+    for m in pending.iter() {
+        let tx = conn.transaction().map_err(|e| error(m.name, e))?;
+        tx.execute_batch(m.sql).map_err(|e| error(m.name, e))?;
+        tx.execute("INSERT INTO schema_migrations (version, name, applied_at) VALUES (?1, ?2, datetime('now'))",
+                   &[&m.version as &dyn ToSql, &m.name])
+            .map_err(|e| error(m.name, e))?;
+        tx.commit().map_err(|e| error(m.name, e))?;
+    }
+    Ok(pending)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn migrations_are_the_files() {
+        let mut files = fs::read_dir("migrations").unwrap()
+            .map(|f| f.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, MIGRATIONS.iter().map(|m| format!("{}.sql", m.name)).collect::<Vec<_>>());
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version as usize, i);
+            assert!(m.name.starts_with(&format!("{:03}-", m.version)));
+        }
+    }
+
+    #[test]
+    fn migrate_once() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(migrate(&mut conn).unwrap().len(), MIGRATIONS.len());
+        assert!(migrate(&mut conn).unwrap().is_empty());
+        assert!(pending(&conn).unwrap().is_empty());
+        let unrecorded: i64 = conn
+            .query_row("SELECT COUNT(*) FROM schema_migrations WHERE applied_at IS NULL", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(unrecorded, 0);
+    }
+
+    #[test]
+    fn refuse_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute("INSERT INTO schema_migrations (version, name) VALUES (1000, '1000-future')", NO_PARAMS).unwrap();
+        assert!(pending(&conn).unwrap_err().contains("newer"));
+        assert!(migrate(&mut conn).is_err());
+
+        conn.execute("DELETE FROM schema_migrations WHERE version = 1000", NO_PARAMS).unwrap();
+        conn.execute("UPDATE schema_migrations SET name = '002-renamed' WHERE version = 2", NO_PARAMS).unwrap();
+        assert!(pending(&conn).unwrap_err().contains("002-renamed"));
+    }
+
+    #[test]
+    fn track_untracked_database() {
+        for untracked in 1..=UNTRACKED_MARKERS.len() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            for m in MIGRATIONS[..untracked].iter() {
+                conn.execute_batch(m.sql).unwrap();
+            }
+            assert_eq!(migrate(&mut conn).unwrap().len(), MIGRATIONS.len() - untracked);
+            let tracked: i64 = conn.query_row("SELECT COUNT(*) FROM schema_migrations", NO_PARAMS, |row| row.get(0)).unwrap();
+            assert_eq!(tracked as usize, MIGRATIONS.len());
+            assert!(pending(&conn).unwrap().is_empty());
+        }
+    }
+}
@@ -1,46 +1,492 @@
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::sync::OnceLock;
 
-use serde::{Deserialize, Serialize};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, NaiveDate, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json::Map;
+use serde_json::Number;
 use serde_json::Value;
 
+use crate::crypto::VerificationError;
+use crate::storage::Resource;
+
 #[derive(Deserialize, Serialize, Clone)]
 pub struct IdObj {
-    pub id: Option<u32>
+    pub id: Option<u64>
 }
 
 #[derive(Deserialize, Serialize, Clone, PartialEq)]
 pub struct Credential {
-    pub id: Option<u32>,
-    pub schema_id: Option<u32>,
-    pub public_key_id: Option<u32>,
+    pub id: Option<u64>,
+    pub schema_id: Option<u64>,
+    /// Version of the schema the credential conforms to, the latest one when created without
+    pub schema_version: Option<u32>,
+    pub public_key_id: Option<u64>,
     pub finger_print: Option<String>,
     pub data: Option<Value>,
 }
 
+/// What a credential's finger_print is expected to sign
+#[derive(Serialize, Clone)]
+pub struct SigningInput {
+    pub id: Option<u64>,
+    pub signing_input: String,
+}
+
 type SchemaBaseType = HashMap<String, SchemaValueType>;
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub struct Schema {
+    pub name: Option<String>,
     pub schema: Option<SchemaBaseType>,
-    pub id: Option<u32>,
+    /// Whether objects may have keys the schema does not list, defaults to true
+    pub additional_properties: Option<bool>,
+    /// Starts at 1, published versions are immutable and updating the schema publishes the next one
+    pub version: Option<u32>,
+    pub id: Option<u64>,
+}
+
+impl Schema {
+    /// Every place the credential's data does not conform to this schema, honouring additional_properties
+    pub fn validate(&self, cred: &Credential) -> Vec<SchemaViolation> {
+        match self.schema {
+            Some(ref sbt) => validate(cred, sbt, self.additional_properties.unwrap_or(true)),
+            None => vec!(),
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
 pub enum SchemaValueType {
     Bool,
     Int,
+    /// Integer from 0 up to u64::MAX
+    UInt,
     Float,
     String,
     Null,
+    /// String holding an RFC 3339 full-date, e.g. `2000-01-31`
+    Date,
+    /// String holding an RFC 3339 date-time, e.g. `2000-01-31T12:00:00Z`
+    DateTime,
+    /// String holding base64 with padding and no trailing bits
+    Bytes,
+    /// String holding an RFC 3986 URI, which has a scheme
+    Uri,
     List(Vec<SchemaValueType>),
+    /// List of any length whose items are all of one type, bound its length with `Constrained`
+    ListOf(Box<SchemaValueType>),
     Map(HashMap<String, SchemaValueType>),
+    /// Object key that must be present, keys may be left out otherwise
+    Required(Box<SchemaValueType>),
+    /// Value of a type further restricted by constraints
+    Constrained(Box<Constraints>),
+    /// Value of any of the types
+    OneOf(Vec<SchemaValueType>),
+    /// Null or a value of the type
+    Nullable(Box<SchemaValueType>),
+}
+
+impl SchemaValueType {
+    /// Checks constraints apply to the type they restrict and patterns are valid regular expressions
+    pub fn check(&self) -> Result<(), String> {
+        match self {
+            SchemaValueType::List(ref ls) => ls.iter().try_for_each(|s| s.check()),
+            SchemaValueType::Map(ref m) => check_schema(m),
+            SchemaValueType::ListOf(box ref inner) => inner.check(),
+            SchemaValueType::Required(box ref inner) => inner.check(),
+            SchemaValueType::Constrained(box ref c) => c.check(),
+            SchemaValueType::OneOf(ref ls) if ls.is_empty() => Err("OneOf needs at least one type".to_owned()),
+            SchemaValueType::OneOf(ref ls) => ls.iter().try_for_each(|s| s.check()),
+            SchemaValueType::Nullable(box ref inner) => inner.check(),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Checks every type in the schema, see `SchemaValueType::check`
+pub fn check_schema(sbt: &SchemaBaseType) -> Result<(), String> {
+    let mut keys: Vec<&String> = sbt.keys().collect();
+    keys.sort();
+    keys.into_iter().try_for_each(|k| sbt[k].check().map_err(|e| format!("{}: {}", k, e)))
+}
+
+/// Restrictions on a value of `type`, unset ones are not checked
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Constraints {
+    #[serde(rename = "type")]
+    pub value_type: SchemaValueType,
+    /// Inclusive lower bound on numbers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minimum: Option<Bound>,
+    /// Inclusive upper bound on numbers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maximum: Option<Bound>,
+    /// Bounds on the number of characters in strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// Regular expression strings must match somewhere, anchor with `^` and `$` to match whole strings
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Values allowed, compared as JSON
+    #[serde(rename = "enum", default, skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<Vec<Value>>,
+    /// Bounds on the number of items in a `ListOf`, a `List` has the length of its types
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_items: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_items: Option<usize>,
+    /// pattern compiled by `check` or on first use, not part of the schema
+    #[serde(skip)]
+    pub compiled_pattern: CompiledPattern,
+}
+
+/// A compiled regular expression kept along the constraints, equal to any other as it follows from pattern
+#[derive(Clone, Debug, Default)]
+pub struct CompiledPattern(OnceLock<Regex>);
+
+impl PartialEq for CompiledPattern {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+/// A number as the schema or the data has it, integers compare exactly with integers and as f64 with floats
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(transparent)]
+pub struct Bound(pub Number);
+
+impl Bound {
+    fn integer(&self) -> Option<i128> {
+        self.0.as_i64().map(i128::from).or_else(|| self.0.as_u64().map(i128::from))
+    }
+}
+
+impl PartialOrd for Bound {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.integer(), other.integer()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => self.0.as_f64()?.partial_cmp(&other.0.as_f64()?),
+        }
+    }
+}
+
+impl PartialEq for Bound {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl Display for Bound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Fails when both bounds are set and min is greater than max
+fn check_bounds<T: PartialOrd + Display>(min: Option<T>, max: Option<T>, min_name: &str, max_name: &str) -> Result<(), String> {
+    match (min, max) {
+        (Some(min), Some(max)) if min > max => Err(format!("{} {} is greater than {} {}", min_name, min, max_name, max)),
+        _ => Ok(()),
+    }
+}
+
+impl Constraints {
+    fn check(&self) -> Result<(), String> {
+        self.value_type.check()?;
+        let numeric = matches!(self.value_type, SchemaValueType::Int | SchemaValueType::UInt | SchemaValueType::Float);
+        let string = matches!(self.value_type, SchemaValueType::String);
+        let list = matches!(self.value_type, SchemaValueType::ListOf(_));
+        if !numeric && (self.minimum.is_some() || self.maximum.is_some()) {
+            return Err("minimum and maximum only apply to Int, UInt and Float".to_owned());
+        }
+        if !string && (self.min_length.is_some() || self.max_length.is_some() || self.pattern.is_some()) {
+            return Err("min_length, max_length and pattern only apply to String".to_owned());
+        }
+        if !list && (self.min_items.is_some() || self.max_items.is_some()) {
+            return Err("min_items and max_items only apply to ListOf".to_owned());
+        }
+        check_bounds(self.minimum.as_ref(), self.maximum.as_ref(), "minimum", "maximum")?;
+        check_bounds(self.min_length, self.max_length, "min_length", "max_length")?;
+        check_bounds(self.min_items, self.max_items, "min_items", "max_items")?;
+        if let Some(ref p) = self.pattern {
+            self.regex(p).map_err(|e| format!("invalid pattern: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// The pattern compiled once, values checked later reuse it
+    fn regex(&self, pattern: &str) -> Result<&Regex, regex::Error> {
+        if let Some(r) = self.compiled_pattern.0.get() {
+            return Ok(r);
+        }
+        let r = Regex::new(pattern)?;
+        Ok(self.compiled_pattern.0.get_or_init(|| r))
+    }
+
+    /// Why the value, already known to be of `value_type`, breaks these constraints
+    fn violations(&self, v: &Value) -> Vec<String> {
+        let mut found = vec!();
+        if let Value::Number(ref n) = v {
+            let n = Bound(n.clone());
+            if let Some(min) = self.minimum.as_ref().filter(|min| n < **min) {
+                found.push(format!("{} is less than the minimum {}", n, min));
+            }
+            if let Some(max) = self.maximum.as_ref().filter(|max| n > **max) {
+                found.push(format!("{} is greater than the maximum {}", n, max));
+            }
+        }
+        if let Some(s) = v.as_str() {
+            let len = s.chars().count();
+            if let Some(min) = self.min_length.filter(|min| len < *min) {
+                found.push(format!("length {} is less than the minimum {}", len, min));
+            }
+            if let Some(max) = self.max_length.filter(|max| len > *max) {
+                found.push(format!("length {} is greater than the maximum {}", len, max));
+            }
+            if let Some(ref p) = self.pattern {
+                match self.regex(p) {
+                    Ok(r) if r.is_match(s) => {}
+                    Ok(_) => found.push(format!("does not match pattern {}", p)),
+                    Err(e) => found.push(format!("invalid pattern: {}", e)),
+                }
+            }
+        }
+        if let Some(arr) = v.as_array() {
+            if let Some(min) = self.min_items.filter(|min| arr.len() < *min) {
+                found.push(format!("{} items is less than the minimum {}", arr.len(), min));
+            }
+            if let Some(max) = self.max_items.filter(|max| arr.len() > *max) {
+                found.push(format!("{} items is greater than the maximum {}", arr.len(), max));
+            }
+        }
+        if let Some(ref allowed) = self.allowed {
+            if !allowed.contains(v) {
+                found.push("value is not one of the allowed values".to_owned());
+            }
+        }
+        found
+    }
 }
 
 #[derive(Deserialize, Serialize, Clone)]
 pub struct CryptographicKeys {
     pub public_key: Option<String>,
-    pub id: Option<u32>,
+    pub algorithm: Option<SignatureAlgorithm>,
+    /// Credentials can no longer be created or verified with a revoked key
+    pub revoked: Option<bool>,
+    /// Credentials can no longer be created or verified with the key after this
+    pub expires_at: Option<DateTime<Utc>>,
+    pub id: Option<u64>,
+}
+
+impl CryptographicKeys {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.unwrap_or(false)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.map_or(false, |t| t <= Utc::now())
+    }
+}
+
+/// A credential, optionally with the key to verify it against instead of the stored key referenced by public_key_id.
+/// The revocation status of an inline key is not checked, its revoked and expires_at are ignored
+#[derive(Deserialize, Clone)]
+pub struct VerifyRequest {
+    #[serde(flatten)]
+    pub credential: Credential,
+    pub public_key: Option<CryptographicKeys>,
+}
+
+/// Reasons for a credential to fail its checks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CredentialError {
+    MissingSchemaId,
+    SchemaNotFound,
+    Nonconforming,
+    MissingPublicKeyId,
+    KeyNotFound,
+    KeyRevoked,
+    KeyExpired,
+    Signature(VerificationError),
+}
+
+impl CredentialError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            CredentialError::MissingSchemaId => "Invalid schema_id field",
+            CredentialError::SchemaNotFound => "No schema found",
+            CredentialError::Nonconforming => "Invalid or Nonconforming schema",
+            CredentialError::MissingPublicKeyId => "Invalid public_key_id field",
+            CredentialError::KeyNotFound => "No key found",
+            CredentialError::KeyRevoked => "Key has been revoked",
+            CredentialError::KeyExpired => "Key has expired",
+            CredentialError::Signature(e) => e.message(),
+        }
+    }
+}
+
+impl Serialize for CredentialError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.message())
+    }
+}
+
+/// Outcome of every check on a credential, checks that could not run for lack of a schema or key are false
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct VerificationReport {
+    pub valid: bool,
+    pub schema_found: bool,
+    /// Version of the schema checked against
+    pub schema_version: Option<u32>,
+    pub schema_conforms: bool,
+    /// Whether the stored key of public_key_id was found, false when the key is inline
+    pub key_found: bool,
+    pub key_inline: bool,
+    pub key_revoked: bool,
+    pub key_expires_at: Option<DateTime<Utc>>,
+    pub key_expired: bool,
+    pub signature_valid: bool,
+    pub errors: Vec<CredentialError>,
+    pub schema_violations: Vec<SchemaViolation>,
+}
+
+/// Holds a private key the server signs issued credentials with
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Issuer {
+    pub algorithm: Option<SignatureAlgorithm>,
+    pub public_key_id: Option<u64>,
+    pub id: Option<u64>,
+    /// Private key sealed by the server's master key, never leaves the server
+    #[serde(skip)]
+    pub private_key: Option<Vec<u8>>,
+}
+
+/// Body of a credential issuance, signed by the issuer's key
+#[derive(Deserialize, Serialize, Clone)]
+pub struct IssueRequest {
+    pub schema_id: Option<u64>,
+    pub issuer_id: Option<u64>,
+    pub data: Option<Value>,
+}
+
+/// Signature algorithm a key is used with, keys declared without one are Ed25519
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum SignatureAlgorithm {
+    #[default]
+    Ed25519,
+    EcdsaP256,
+    Secp256k1,
+    RsaPss,
+}
+
+impl SignatureAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignatureAlgorithm::Ed25519 => "Ed25519",
+            SignatureAlgorithm::EcdsaP256 => "EcdsaP256",
+            SignatureAlgorithm::Secp256k1 => "Secp256k1",
+            SignatureAlgorithm::RsaPss => "RsaPss",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "Ed25519" => Some(SignatureAlgorithm::Ed25519),
+            "EcdsaP256" => Some(SignatureAlgorithm::EcdsaP256),
+            "Secp256k1" => Some(SignatureAlgorithm::Secp256k1),
+            "RsaPss" => Some(SignatureAlgorithm::RsaPss),
+            _ => None,
+        }
+    }
+}
+
+/// What deleting an object credentials still reference does to them
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum OnDelete {
+    /// Refuse to delete the object
+    #[default]
+    Restrict,
+    /// Delete the credentials along with the object
+    Cascade,
+}
+
+impl OnDelete {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "restrict" => Some(OnDelete::Restrict),
+            "cascade" => Some(OnDelete::Cascade),
+            _ => None,
+        }
+    }
+}
+
+/// Order of list endpoints, lists are sorted by id
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum Sort {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+impl Sort {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(Sort::Ascending),
+            "-id" => Some(Sort::Descending),
+            _ => None,
+        }
+    }
+}
+
+/// The part of a list to return, limit and offset apply after sorting
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct Page {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub sort: Sort,
+}
+
+/// Filters of the credential list, None matches any
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct CredentialFilter {
+    pub schema_id: Option<u64>,
+    pub public_key_id: Option<u64>,
+}
+
+impl CredentialFilter {
+    pub fn matches(&self, credential: &Credential) -> bool {
+        self.schema_id.map_or(true, |id| credential.schema_id == Some(id))
+            && self.public_key_id.map_or(true, |id| credential.public_key_id == Some(id))
+    }
+}
+
+/// Filters of the cryptographic key list, None matches any
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub struct KeyFilter {
+    pub algorithm: Option<SignatureAlgorithm>,
+    pub revoked: Option<bool>,
+}
+
+impl KeyFilter {
+    pub fn matches(&self, key: &CryptographicKeys) -> bool {
+        self.algorithm.map_or(true, |a| key.algorithm.unwrap_or_default() == a)
+            && self.revoked.map_or(true, |r| key.is_revoked() == r)
+    }
+}
+
+/// Credentials preventing an object from being deleted
+#[derive(Serialize, Clone)]
+pub struct Dependents {
+    pub credential_ids: Vec<u64>,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -48,161 +494,286 @@ pub struct ErrorMessage {
     pub error: &'static str,
 }
 
-/// Checks whether the credentials conforms to our schema
-pub fn conforms(cred: &Credential, sbt: &SchemaBaseType) -> bool {
-    return match cred.data {
-        Some(Value::Object(ref m)) => {
-            map_verify(m, sbt)
+/// ErrorMessage with extra information on what went wrong
+#[derive(Serialize, Clone)]
+pub struct DetailedErrorMessage<T: Serialize> {
+    pub error: &'static str,
+    pub details: T,
+}
+
+/// A place where credential data does not conform to its schema
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct SchemaViolation {
+    /// JSON Pointer (RFC 6901) to the offending value within the credential's data
+    pub path: String,
+    /// What the schema expects there, None when the schema has no such key
+    pub expected: Option<SchemaValueType>,
+    /// JSON type found there
+    pub actual: &'static str,
+    pub message: String,
+    /// Violations of each alternative of a OneOf, in schema order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub branches: Vec<Vec<SchemaViolation>>,
+}
+
+impl SchemaViolation {
+    fn new(path: &str, expected: Option<&SchemaValueType>, actual: &Value, message: String) -> Self {
+        SchemaViolation {
+            path: path.to_owned(),
+            expected: expected.cloned(),
+            actual: json_type(actual),
+            message,
+            branches: vec!(),
         }
-        _ => { false }
     }
 }
 
-fn verify_single(v: &Value, sbt: Option<&SchemaValueType>) -> bool {
-    if sbt.is_none() {
-        return false;
-    }
-    let sbt = sbt.unwrap();
+/// Name of the value's JSON type, numbers are told apart into integer and number
+fn json_type(v: &Value) -> &'static str {
     match v {
-        Value::Array(ref arr) => {
-            return if let SchemaValueType::List(ref ls) = sbt {
-                vec_verify(arr, ls)
-            } else {
-                false
-            };
-        }
-        Value::Object(ref m) => {
-            return if let SchemaValueType::Map(ref ls) = sbt {
-                map_verify(m, ls)
-            } else {
-                false
-            };
-        }
-        Value::Bool(_) => {
-            if let SchemaValueType::Bool = sbt {} else {
-                return false;
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(ref n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Appends a reference token to a JSON Pointer
+pub(crate) fn pointer_push(path: &str, token: &str) -> String {
+    format!("{}/{}", path, token.replace('~', "~0").replace('/', "~1"))
+}
+
+/// Every place the credential's data does not conform to our schema,
+/// additional_properties tells whether objects may have keys the schema does not list
+pub fn validate(cred: &Credential, sbt: &SchemaBaseType, additional_properties: bool) -> Vec<SchemaViolation> {
+    Validator::new(additional_properties).validate(cred, sbt)
+}
+
+/// Walks data alongside its schema collecting violations
+struct Validator {
+    additional_properties: bool,
+    violations: Vec<SchemaViolation>,
+}
+
+impl Validator {
+    fn new(additional_properties: bool) -> Self {
+        Validator { additional_properties, violations: vec!() }
+    }
+
+    fn validate(mut self, cred: &Credential, sbt: &SchemaBaseType) -> Vec<SchemaViolation> {
+        match cred.data {
+            Some(Value::Object(ref m)) => {
+                self.map_verify(m, sbt, "")
             }
-        }
-        Value::Number(ref n) => {
-            return match sbt {
-                SchemaValueType::Int => { n.is_i64() }
-                SchemaValueType::Float => { n.is_f64() }
-                _ => { false }
+            ref v => {
+                self.violations.push(SchemaViolation::new(
+                    "", Some(&SchemaValueType::Map(sbt.clone())), v.as_ref().unwrap_or(&Value::Null),
+                    "data must be an object".to_owned()))
             }
         }
-        Value::String(_) => {
-            if let SchemaValueType::String = sbt {} else {
-                return false;
+        self.violations
+    }
+
+    fn verify_single(&mut self, v: &Value, sbt: &SchemaValueType, path: &str) {
+        let matches = match (v, sbt) {
+            (_, SchemaValueType::Required(box ref inner)) => {
+                return self.verify_single(v, inner, path);
             }
-        }
-        Value::Null => {
-            if let SchemaValueType::Null = sbt {} else {
-                return false;
+            (_, SchemaValueType::Constrained(box ref c)) => {
+                let before = self.violations.len();
+                self.verify_single(v, &c.value_type, path);
+                if self.violations.len() == before {
+                    for message in c.violations(v) {
+                        self.violations.push(SchemaViolation::new(path, Some(sbt), v, message));
+                    }
+                }
+                return;
+            }
+            (Value::Null, SchemaValueType::Nullable(_)) => true,
+            (_, SchemaValueType::Nullable(box ref inner)) => {
+                return self.verify_single(v, inner, path);
+            }
+            (_, SchemaValueType::OneOf(ref ls)) => {
+                return self.one_of_verify(v, sbt, ls, path);
+            }
+            (Value::Array(ref arr), SchemaValueType::List(ref ls)) => {
+                return self.vec_verify(arr, ls, path);
             }
+            (Value::Array(ref arr), SchemaValueType::ListOf(box ref item)) => {
+                for (n, i) in arr.iter().enumerate() {
+                    self.verify_single(i, item, &pointer_push(path, &n.to_string()));
+                }
+                return;
+            }
+            (Value::Object(ref m), SchemaValueType::Map(ref ls)) => {
+                return self.map_verify(m, ls, path);
+            }
+            (Value::Bool(_), SchemaValueType::Bool) => true,
+            (Value::Number(ref n), SchemaValueType::Int) => n.is_i64(),
+            (Value::Number(ref n), SchemaValueType::UInt) => n.is_u64(),
+            (Value::Number(ref n), SchemaValueType::Float) => n.is_f64(),
+            (Value::String(_), SchemaValueType::String) => true,
+            (Value::String(ref s), SchemaValueType::Date) => {
+                s.len() == 10 && NaiveDate::parse_from_str(s, "%Y-%m-%d").is_ok()
+            }
+            (Value::String(ref s), SchemaValueType::DateTime) => DateTime::parse_from_rfc3339(s).is_ok(),
+            (Value::String(ref s), SchemaValueType::Bytes) => BASE64.decode(s).is_ok(),
+            (Value::String(ref s), SchemaValueType::Uri) => fluent_uri::Uri::parse(s.as_str()).is_ok(),
+            (Value::Null, SchemaValueType::Null) => true,
+            _ => false,
+        };
+        if !matches {
+            self.violations.push(SchemaViolation::new(path, Some(sbt), v, "type mismatch".to_owned()));
         }
     }
-    true
-}
 
-fn vec_verify(m: &Vec<Value>, sbt: &Vec<SchemaValueType>) -> bool {
-    if m.len() != sbt.len() {
-        return false;
+    fn one_of_verify(&mut self, v: &Value, sbt: &SchemaValueType, ls: &[SchemaValueType], path: &str) {
+        let mut branches = vec!();
+        for s in ls {
+            let mut branch = Validator::new(self.additional_properties);
+            branch.verify_single(v, s, path);
+            if branch.violations.is_empty() {
+                return;
+            }
+            branches.push(branch.violations);
+        }
+        self.violations.push(SchemaViolation {
+            branches,
+            ..SchemaViolation::new(path, Some(sbt), v, "matches none of the types".to_owned())
+        });
     }
-    for (n, i) in m.iter().enumerate() {
-        if !verify_single(i, sbt.get(n)) {
-            return false;
+
+    fn vec_verify(&mut self, m: &[Value], sbt: &[SchemaValueType], path: &str) {
+        if m.len() != sbt.len() {
+            self.violations.push(SchemaViolation::new(
+                path, Some(&SchemaValueType::List(sbt.to_vec())), &Value::Array(m.to_vec()),
+                format!("expected {} items, found {}", sbt.len(), m.len())));
+        }
+        for (n, (i, s)) in m.iter().zip(sbt).enumerate() {
+            self.verify_single(i, s, &pointer_push(path, &n.to_string()));
         }
     }
-    true
-}
 
-fn map_verify(m: &Map<String, Value>, sbt: &SchemaBaseType) -> bool {
-    for i in m.iter() {
-        if !verify_single(i.1, sbt.get(i.0)) {
-            return false;
+    fn map_verify(&mut self, m: &Map<String, Value>, sbt: &SchemaBaseType, path: &str) {
+        for i in m.iter() {
+            match sbt.get(i.0) {
+                Some(s) => self.verify_single(i.1, s, &pointer_push(path, i.0)),
+                None if self.additional_properties => {}
+                None => self.violations.push(SchemaViolation::new(
+                    &pointer_push(path, i.0), None, i.1, "key is not in schema".to_owned())),
+            }
+        }
+        let mut missing: Vec<(&String, &SchemaValueType)> = sbt.iter()
+            .filter(|s| !m.contains_key(s.0) && matches!(s.1, SchemaValueType::Required(_)))
+            .collect();
+        missing.sort_by_key(|s| s.0);
+        for s in missing {
+            self.violations.push(SchemaViolation {
+                path: pointer_push(path, s.0),
+                expected: Some(s.1.clone()),
+                actual: "missing",
+                message: "required key is missing".to_owned(),
+                branches: vec!(),
+            });
         }
     }
-    true
 }
 
 pub trait WithID {
-    fn new_with_new_id(&self, _: u32) -> Self;
+    fn new_with_new_id(&self, _: u64) -> Self;
+    fn get_id(&self) -> Option<u64>;
 }
 
 impl WithID for Schema {
-    fn new_with_new_id(&self, i: u32) -> Self {
+    fn new_with_new_id(&self, i: u64) -> Self {
         Schema {
             id: Some(i),
+            name: self.name.clone(),
             schema: self.schema.clone(),
+            additional_properties: self.additional_properties,
+            version: self.version,
         }
     }
+
+    fn get_id(&self) -> Option<u64> {
+        self.id
+    }
 }
 
 impl WithID for CryptographicKeys {
-    fn new_with_new_id(&self, i: u32) -> Self {
+    fn new_with_new_id(&self, i: u64) -> Self {
         CryptographicKeys {
             id: Some(i),
             public_key: self.public_key.clone(),
+            algorithm: self.algorithm,
+            revoked: self.revoked,
+            expires_at: self.expires_at,
         }
     }
-}
 
-impl WithID for Credential {
-    fn new_with_new_id(&self, i: u32) -> Self {
-        Credential {
-            id: Some(i),
-            finger_print: self.finger_print.clone(),
-            data: self.data.clone(),
-            public_key_id: self.public_key_id.clone(),
-            schema_id: self.schema_id.clone(),
-        }
+    fn get_id(&self) -> Option<u64> {
+        self.id
     }
 }
 
-pub trait Clean {
-    fn new_clean(_: u32) -> Self;
-}
-
-impl Clean for Schema {
-    fn new_clean(i: u32) -> Self {
-        Schema {
+impl WithID for Issuer {
+    fn new_with_new_id(&self, i: u64) -> Self {
+        Issuer {
             id: Some(i),
-            schema: None,
+            algorithm: self.algorithm,
+            public_key_id: self.public_key_id,
+            private_key: self.private_key.clone(),
         }
     }
-}
 
-impl Clean for CryptographicKeys {
-    fn new_clean(i: u32) -> Self {
-        CryptographicKeys {
-            id: Some(i),
-            public_key: None,
-        }
+    fn get_id(&self) -> Option<u64> {
+        self.id
     }
 }
 
-impl Clean for Credential {
-    fn new_clean(i: u32) -> Self {
+impl WithID for Credential {
+    fn new_with_new_id(&self, i: u64) -> Self {
         Credential {
             id: Some(i),
-            finger_print: None,
-            data: None,
-            public_key_id: None,
-            schema_id: None,
+            finger_print: self.finger_print.clone(),
+            data: self.data.clone(),
+            public_key_id: self.public_key_id,
+            schema_id: self.schema_id,
+            schema_version: self.schema_version,
         }
     }
+
+    fn get_id(&self) -> Option<u64> {
+        self.id
+    }
+}
+
+pub trait ProjectData<'a>: Serialize + Deserialize<'a> + WithID {
+    /// What the object is stored as
+    const RESOURCE: Resource;
 }
 
-pub trait ProjectData<'a>: Serialize + Deserialize<'a> + WithID + Clean {}
+impl ProjectData<'_> for Credential {
+    const RESOURCE: Resource = Resource::Credentials;
+}
 
-impl ProjectData<'_> for Credential {}
+impl ProjectData<'_> for CryptographicKeys {
+    const RESOURCE: Resource = Resource::CryptographicKeys;
+}
 
-impl ProjectData<'_> for CryptographicKeys {}
+impl ProjectData<'_> for Schema {
+    const RESOURCE: Resource = Resource::Schemas;
+}
 
-impl ProjectData<'_> for Schema {}
+impl ProjectData<'_> for Issuer {
+    const RESOURCE: Resource = Resource::Issuers;
+}
 
 #[test]
-fn smoke_test_conform_pass() {
+fn smoke_test_validate_pass() {
     let schema: SchemaBaseType = serde_json::from_str(
         "{
     \"a\": \"Bool\",
@@ -233,19 +804,20 @@ fn smoke_test_conform_pass() {
     }
   ]
 }").unwrap();
-    assert!(conforms( &{ Credential {
+    assert!(validate( &{ Credential {
         id: None,
         schema_id: None,
+        schema_version: None,
         public_key_id: None,
         finger_print: None,
         data: Some(data),
     }
-    }, &schema));
+    }, &schema, true).is_empty());
 
 }
 
 #[test]
-fn smoke_test_conform_fail() {
+fn smoke_test_validate_fail() {
     let schema: SchemaBaseType = serde_json::from_str(
         "{
     \"a\": \"Bool\",
@@ -276,20 +848,21 @@ fn smoke_test_conform_fail() {
     }
   ]
 }").unwrap();
-    assert_eq!(false, conforms( &{ Credential {
+    assert!(!validate( &{ Credential {
         id: None,
         schema_id: None,
+        schema_version: None,
         public_key_id: None,
         finger_print: None,
         data: Some(data),
     }
-    }, &schema));
+    }, &schema, true).is_empty());
 
 }
 
 #[test]
 /// Will accept extra args in a json Object
-fn smoke_test_conform_extra_pass() {
+fn smoke_test_validate_extra_pass() {
     let schema: SchemaBaseType = serde_json::from_str(
         "{
     \"a\": \"Bool\",
@@ -321,21 +894,22 @@ fn smoke_test_conform_extra_pass() {
     }
   ]
 }").unwrap();
-    assert_eq!(true, conforms( &{ Credential {
+    assert!(validate( &{ Credential {
         id: None,
         schema_id: None,
+        schema_version: None,
         public_key_id: None,
         finger_print: None,
         data: Some(data),
     }
-    }, &schema));
+    }, &schema, true).is_empty());
 
 }
 
 
 #[test]
 /// Will accept extra args in a json Object
-fn smoke_test_conform_vec_extra_fail() {
+fn smoke_test_validate_vec_extra_fail() {
     let schema: SchemaBaseType = serde_json::from_str(
         "{
     \"a\": \"Bool\",
@@ -367,14 +941,259 @@ fn smoke_test_conform_vec_extra_fail() {
     }
   ]
 }").unwrap();
-    assert_eq!(false, conforms( &{ Credential {
+    assert!(!validate( &{ Credential {
         id: None,
         schema_id: None,
+        schema_version: None,
         public_key_id: None,
         finger_print: None,
         data: Some(data),
     }
-    }, &schema));
+    }, &schema, true).is_empty());
+
+}
+
+
+/// A credential of the data with nothing else set
+#[cfg(test)]
+fn credential(data: &str) -> Credential {
+    Credential {
+        id: None,
+        schema_id: None,
+        schema_version: None,
+        public_key_id: None,
+        finger_print: None,
+        data: Some(serde_json::from_str(data).unwrap()),
+    }
+}
+
+#[test]
+fn validate_reports_paths() {
+    let schema: SchemaBaseType = serde_json::from_str(
+        "{
+    \"a\": \"Bool\",
+    \"b/c\": \"Int\",
+    \"d\": {
+      \"List\": [
+        {
+          \"Map\": {
+            \"e\": \"Float\"
+          }
+        }
+      ]
+    }
+  }").unwrap();
+    let data =
+        "{
+  \"a\": \"yes\",
+  \"b/c\": 1.5,
+  \"d\": [
+    {
+      \"e\": 1.1,
+      \"f\": null
+    },
+    true
+  ]
+}";
+    let violations = validate(&credential(data), &schema, false);
+    let found: Vec<(&str, Option<&SchemaValueType>, &str)> = violations.iter()
+        .map(|v| (v.path.as_str(), v.expected.as_ref(), v.actual)).collect();
+    assert_eq!(found, vec!(
+        ("/a", Some(&SchemaValueType::Bool), "string"),
+        ("/b~1c", Some(&SchemaValueType::Int), "number"),
+        ("/d", schema.get("d"), "array"),
+        ("/d/0/f", None, "null"),
+    ));
+}
+
+
+#[test]
+fn validate_required_and_strict() {
+    let sbt: SchemaBaseType = serde_json::from_str(
+        "{
+    \"a\": {\"Required\": \"Bool\"},
+    \"b\": \"Int\"
+  }").unwrap();
+    let lax = Schema { name: None, schema: Some(sbt.clone()), additional_properties: None, version: None, id: None };
+    let strict = Schema { name: None, schema: Some(sbt), additional_properties: Some(false), version: None, id: None };
+
+    assert!(lax.validate(&credential("{\"a\": true}")).is_empty());
+    assert!(lax.validate(&credential("{\"a\": true, \"b\": 1, \"c\": null}")).is_empty());
+    assert!(!lax.validate(&credential("{\"a\": true, \"b\": null}")).is_empty());
+
+    let missing = lax.validate(&credential("{\"b\": 1}"));
+    assert_eq!(missing.len(), 1);
+    assert_eq!((missing[0].path.as_str(), missing[0].actual), ("/a", "missing"));
+
+    assert!(strict.validate(&credential("{\"a\": true}")).is_empty());
+    let extra = strict.validate(&credential("{\"a\": true, \"c\": null}"));
+    assert_eq!(extra.len(), 1);
+    assert_eq!((extra[0].path.as_str(), extra[0].expected.as_ref()), ("/c", None));
+}
+
+
+#[test]
+fn validate_keys_optional_unless_required() {
+    let sbt: SchemaBaseType = serde_json::from_str(
+        "{
+    \"a\": \"Bool\",
+    \"b\": {\"Map\": {\"c\": {\"Required\": \"Bool\"}, \"d\": \"Int\"}}
+  }").unwrap();
+    assert!(validate(&credential("{}"), &sbt, true).is_empty());
+    assert!(validate(&credential("{\"b\": {\"c\": true}}"), &sbt, true).is_empty());
+
+    let missing = validate(&credential("{\"a\": true, \"b\": {\"d\": 1}}"), &sbt, true);
+    assert_eq!(missing.len(), 1);
+    assert_eq!((missing[0].path.as_str(), missing[0].actual), ("/b/c", "missing"));
+}
+
+#[test]
+fn validate_constraints() {
+    let sbt: SchemaBaseType = serde_json::from_str(
+        "{
+    \"birth_year\": {\"Constrained\": {\"type\": \"Int\", \"minimum\": 0}},
+    \"country\": {\"Constrained\": {\"type\": \"String\", \"enum\": [\"AU\", \"NZ\"]}},
+    \"name\": {\"Constrained\": {\"type\": \"String\", \"min_length\": 1, \"max_length\": 4, \"pattern\": \"^[a-z]+$\"}},
+    \"tags\": {\"Constrained\": {\"type\": {\"ListOf\": \"String\"}, \"max_items\": 1}}
+  }").unwrap();
+    assert!(check_schema(&sbt).is_ok());
+    assert!(validate(&credential("{\"birth_year\": 1990, \"country\": \"NZ\", \"name\": \"ed\"}"), &sbt, true).is_empty());
+
+    let violations = validate(&credential("{\"birth_year\": -1, \"country\": \"US\", \"name\": \"Edward\", \"tags\": [\"a\", \"b\"]}"), &sbt, true);
+    let found: Vec<(&str, &str)> = violations.iter().map(|v| (v.path.as_str(), v.message.as_str())).collect();
+    let mut found_sorted = found.clone();
+    found_sorted.sort();
+    assert_eq!(found_sorted, vec!(
+        ("/birth_year", "-1 is less than the minimum 0"),
+        ("/country", "value is not one of the allowed values"),
+        ("/name", "does not match pattern ^[a-z]+$"),
+        ("/name", "length 6 is greater than the maximum 4"),
+        ("/tags", "2 items is greater than the maximum 1"),
+    ));
+
+    let type_mismatch = validate(&credential("{\"birth_year\": \"old\", \"country\": \"AU\", \"name\": \"ed\"}"), &sbt, true);
+    assert_eq!(type_mismatch.len(), 1);
+    assert_eq!(type_mismatch[0].message, "type mismatch");
+}
+
+
+#[test]
+fn check_schema_rejects_misplaced_constraints() {
+    let bad = |raw: &str| check_schema(&serde_json::from_str(raw).unwrap()).is_err();
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": \"String\", \"minimum\": 0}}}"));
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": \"Int\", \"pattern\": \"x\"}}}"));
+    assert!(bad("{\"a\": {\"Map\": {\"b\": {\"Constrained\": {\"type\": \"String\", \"pattern\": \"(\"}}}}}"));
+    assert!(!bad("{\"a\": {\"Constrained\": {\"type\": \"Float\", \"maximum\": 1.5}}}"));
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": {\"List\": [\"Int\"]}, \"max_items\": 1}}}"));
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": \"Int\", \"minimum\": 2, \"maximum\": 1}}}"));
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": \"String\", \"min_length\": 2, \"max_length\": 1}}}"));
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": {\"ListOf\": \"Int\"}, \"min_items\": 2, \"max_items\": 1}}}"));
+    assert!(!bad("{\"a\": {\"Constrained\": {\"type\": \"Int\", \"minimum\": 1, \"maximum\": 1}}}"));
+    assert!(bad("{\"a\": {\"Constrained\": {\"type\": \"UInt\", \"minimum\": 9007199254740993, \"maximum\": 9007199254740992}}}"));
+}
+
+
+#[test]
+fn validate_bounds_beyond_f64() {
+    // 2^53 + 1 rounds to 2^53 as f64
+    let sbt: SchemaBaseType = serde_json::from_str(
+        "{
+    \"id\": {\"Constrained\": {\"type\": \"UInt\", \"maximum\": 9007199254740992}},
+    \"offset\": {\"Constrained\": {\"type\": \"Int\", \"minimum\": -9007199254740992}},
+    \"ratio\": {\"Constrained\": {\"type\": \"Float\", \"maximum\": 1.5}}
+  }").unwrap();
+    assert!(validate(&credential("{\"id\": 9007199254740992, \"offset\": -9007199254740992, \"ratio\": 1.5}"), &sbt, true).is_empty());
+
+    let violations = validate(&credential("{\"id\": 9007199254740993, \"offset\": -9007199254740993, \"ratio\": 2.5}"), &sbt, true);
+    let mut found: Vec<&str> = violations.iter().map(|v| v.message.as_str()).collect();
+    found.sort();
+    assert_eq!(found, vec!(
+        "-9007199254740993 is less than the minimum -9007199254740992",
+        "2.5 is greater than the maximum 1.5",
+        "9007199254740993 is greater than the maximum 9007199254740992",
+    ));
+}
+
+
+#[test]
+fn validate_list_of() {
+    let sbt: SchemaBaseType = serde_json::from_str(
+        "{
+    \"parents\": {\"ListOf\": {\"Map\": {\"name\": \"String\"}}},
+    \"scores\": {\"Constrained\": {\"type\": {\"ListOf\": \"Int\"}, \"min_items\": 1, \"max_items\": 3}}
+  }").unwrap();
+    assert!(check_schema(&sbt).is_ok());
+    assert!(validate(&credential("{\"parents\": [], \"scores\": [1]}"), &sbt, true).is_empty());
+    assert!(validate(&credential("{\"parents\": [{\"name\": \"a\"}, {\"name\": \"b\"}], \"scores\": [1, 2, 3]}"), &sbt, true).is_empty());
 
+    let violations = validate(&credential("{\"parents\": [{\"name\": \"a\"}, {\"name\": 2}], \"scores\": []}"), &sbt, true);
+    let mut found: Vec<(&str, &str)> = violations.iter().map(|v| (v.path.as_str(), v.message.as_str())).collect();
+    found.sort();
+    assert_eq!(found, vec!(
+        ("/parents/1/name", "type mismatch"),
+        ("/scores", "0 items is less than the minimum 1"),
+    ));
+    assert!(!validate(&credential("{\"parents\": {}, \"scores\": [1]}"), &sbt, true).is_empty());
 }
 
+
+#[test]
+fn validate_semantic_types() {
+    let check = |ty: &str, value: &str| {
+        let sbt: SchemaBaseType = serde_json::from_str(&format!("{{\"a\": \"{}\"}}", ty)).unwrap();
+        validate(&credential(&format!("{{\"a\": {}}}", value)), &sbt, true).is_empty()
+    };
+    assert!(check("UInt", "0"));
+    assert!(check("UInt", "18446744073709551615"));
+    assert!(!check("UInt", "-1"));
+    assert!(!check("UInt", "1.0"));
+
+    assert!(check("Date", "\"2000-02-29\""));
+    assert!(!check("Date", "\"2001-02-29\""));
+    assert!(!check("Date", "\"2000-2-9\""));
+    assert!(!check("Date", "\"2000-02-29T00:00:00Z\""));
+
+    assert!(check("DateTime", "\"2000-01-31T12:00:00Z\""));
+    assert!(check("DateTime", "\"2000-01-31T12:00:00.5+10:00\""));
+    assert!(!check("DateTime", "\"2000-01-31\""));
+    assert!(!check("DateTime", "\"2000-01-31 12:00\""));
+
+    assert!(check("Bytes", "\"aGVsbG8=\""));
+    assert!(check("Bytes", "\"\""));
+    assert!(!check("Bytes", "\"aGVsbG8\""));
+    assert!(!check("Bytes", "\"aGVsbG9=\""));
+    assert!(!check("Bytes", "\"aGVs bG8=\""));
+
+    assert!(check("Uri", "\"https://example.com/a?b=c#d\""));
+    assert!(check("Uri", "\"urn:isbn:0451450523\""));
+    assert!(!check("Uri", "\"/relative/path\""));
+    assert!(!check("Uri", "\"http://exa mple.com\""));
+    assert!(!check("Uri", "1"));
+}
+
+
+#[test]
+fn validate_one_of_and_nullable() {
+    let sbt: SchemaBaseType = serde_json::from_str(
+        "{
+    \"middle_name\": {\"Required\": {\"Nullable\": \"String\"}},
+    \"height\": {\"OneOf\": [\"Int\", {\"Constrained\": {\"type\": \"Float\", \"minimum\": 0}}]}
+  }").unwrap();
+    assert!(check_schema(&sbt).is_ok());
+    assert!(check_schema(&serde_json::from_str("{\"a\": {\"OneOf\": []}}").unwrap()).is_err());
+    assert!(validate(&credential("{\"middle_name\": null, \"height\": 180}"), &sbt, true).is_empty());
+    assert!(validate(&credential("{\"middle_name\": \"Jo\", \"height\": 180.5}"), &sbt, true).is_empty());
+    assert!(!validate(&credential("{\"height\": 180}"), &sbt, true).is_empty());
+
+    let violations = validate(&credential("{\"middle_name\": 1, \"height\": -1.5}"), &sbt, true);
+    let mut found: Vec<(&str, &str)> = violations.iter().map(|v| (v.path.as_str(), v.message.as_str())).collect();
+    found.sort();
+    assert_eq!(found, vec!(
+        ("/height", "matches none of the types"),
+        ("/middle_name", "type mismatch"),
+    ));
+    let height = violations.iter().find(|v| v.path == "/height").unwrap();
+    let branches: Vec<Vec<&str>> = height.branches.iter()
+        .map(|b| b.iter().map(|v| v.message.as_str()).collect()).collect();
+    assert_eq!(branches, vec!(vec!("type mismatch"), vec!("-1.5 is less than the minimum 0")));
+}
@@ -0,0 +1,117 @@
+//! JSON Canonicalization Scheme (RFC 8785)
+//!
+//! Produces the one serialisation of a JSON value both the issuer and this service sign over:
+//! no whitespace, object keys sorted by their UTF-16 code units, strings with minimal escaping
+//! and numbers formatted as ECMAScript does for IEEE 754 doubles.
+
+use std::fmt::Write;
+
+use serde_json::{Map, Number, Value};
+
+/// Canonical serialisation of the value
+pub fn canonicalize(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value);
+    out
+}
+
+fn write_value(out: &mut String, value: &Value) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) => write_number(out, n),
+        Value::String(s) => write_string(out, s),
+        Value::Array(arr) => {
+            out.push('[');
+            for (n, i) in arr.iter().enumerate() {
+                if n > 0 {
+                    out.push(',');
+                }
+                write_value(out, i);
+            }
+            out.push(']');
+        }
+        Value::Object(m) => write_object(out, m),
+    }
+}
+
+fn write_object(out: &mut String, m: &Map<String, Value>) {
+    let mut entries: Vec<(&String, &Value)> = m.iter().collect();
+    entries.sort_by(|a, b| a.0.encode_utf16().cmp(b.0.encode_utf16()));
+    out.push('{');
+    for (n, (k, v)) in entries.into_iter().enumerate() {
+        if n > 0 {
+            out.push(',');
+        }
+        write_string(out, k);
+        out.push(':');
+        write_value(out, v);
+    }
+    out.push('}');
+}
+
+/// All numbers are treated as doubles, integers beyond 2^53 lose precision as they would in JavaScript
+fn write_number(out: &mut String, n: &Number) {
+    let f = n.as_f64().unwrap();
+    out.push_str(ryu_js::Buffer::new().format_finite(f));
+}
+
+fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{08}' => out.push_str("\\b"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\u{0C}' => out.push_str("\\f"),
+            '\r' => out.push_str("\\r"),
+            c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn canonical(raw: &str) -> String {
+        canonicalize(&serde_json::from_str::<Value>(raw).unwrap())
+    }
+
+    #[test]
+    fn rfc8785_sample() {
+        let raw = r#"{
+  "numbers": [333333333.33333329, 1E30, 4.50, 2e-3, 0.000000000000000000000000001],
+  "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+  "literals": [null, true, false]
+}"#;
+        assert_eq!(canonical(raw),
+                   r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#);
+    }
+
+    #[test]
+    fn rfc8785_key_order() {
+        let raw = r#"{
+  "\u20ac": "Euro Sign",
+  "\r": "Carriage Return",
+  "\ufb33": "Hebrew Letter Dalet With Dagesh",
+  "1": "One",
+  "\ud83d\ude00": "Emoji: Grinning Face",
+  "\u0080": "Control",
+  "\u00f6": "Latin Small Letter O With Diaeresis"
+}"#;
+        assert_eq!(canonical(raw),
+                   "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"\u{80}\":\"Control\",\"ö\":\"Latin Small Letter O With Diaeresis\",\
+                   \"€\":\"Euro Sign\",\"😀\":\"Emoji: Grinning Face\",\"\u{fb33}\":\"Hebrew Letter Dalet With Dagesh\"}");
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(canonical("[0, -0, 1.0, -1.5, 1e21, 1e-7, 123456789012, 9007199254740993]"),
+                   "[0,0,1,-1.5,1e+21,1e-7,123456789012,9007199254740992]");
+    }
+}
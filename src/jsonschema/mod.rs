@@ -0,0 +1,467 @@
+//! Conversion between schemas and JSON Schema (draft 2020-12)
+//!
+//! Every `SchemaValueType` has a JSON Schema rendering, but only the subset of JSON Schema that maps back onto
+//! `SchemaValueType` can be imported, anything else is rejected naming the offending keyword.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::datastructures::{check_schema, pointer_push, Bound, Constraints, Schema, SchemaValueType};
+
+pub const DIALECT: &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Keywords with no bearing on validation, ignored on import
+const ANNOTATIONS: [&str; 10] = [
+    "$schema", "$id", "$comment", "title", "description", "examples", "default", "deprecated", "readOnly", "writeOnly",
+];
+
+const KEYWORDS: [&str; 17] = [
+    "type", "anyOf", "enum", "minimum", "maximum", "minLength", "maxLength", "pattern", "format", "contentEncoding",
+    "items", "prefixItems", "minItems", "maxItems", "properties", "required", "additionalProperties",
+];
+
+/// Why a JSON Schema document could not be imported
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct ImportError {
+    /// JSON Pointer (RFC 6901) to the offending subschema within the document
+    pub path: String,
+    pub message: String,
+}
+
+impl ImportError {
+    fn new(path: &str, message: String) -> Self {
+        ImportError { path: path.to_owned(), message }
+    }
+}
+
+/// JSON Schema document equivalent to the schema, titled with its name
+pub fn export(schema: &Schema) -> Value {
+    let additional_properties = schema.additional_properties.unwrap_or(true);
+    let empty = HashMap::new();
+    let sbt = schema.schema.as_ref().unwrap_or(&empty);
+    let mut root = Map::new();
+    root.insert("$schema".to_owned(), json!(DIALECT));
+    if let Some(ref name) = schema.name {
+        root.insert("title".to_owned(), json!(name));
+    }
+    root.extend(export_type(&SchemaValueType::Map(sbt.clone()), additional_properties));
+    Value::Object(root)
+}
+
+fn export_type(sbt: &SchemaValueType, additional_properties: bool) -> Map<String, Value> {
+    let value = match sbt {
+        SchemaValueType::Bool => json!({"type": "boolean"}),
+        SchemaValueType::Int => json!({"type": "integer"}),
+        SchemaValueType::UInt => json!({"type": "integer", "minimum": 0, "maximum": u64::MAX}),
+        SchemaValueType::Float => json!({"type": "number"}),
+        SchemaValueType::String => json!({"type": "string"}),
+        SchemaValueType::Null => json!({"type": "null"}),
+        SchemaValueType::Date => json!({"type": "string", "format": "date"}),
+        SchemaValueType::DateTime => json!({"type": "string", "format": "date-time"}),
+        SchemaValueType::Bytes => json!({"type": "string", "contentEncoding": "base64"}),
+        SchemaValueType::Uri => json!({"type": "string", "format": "uri"}),
+        SchemaValueType::List(ref ls) => json!({
+            "type": "array",
+            "prefixItems": ls.iter().map(|s| export_type(s, additional_properties)).collect::<Vec<_>>(),
+            "items": false,
+            "minItems": ls.len(),
+        }),
+        SchemaValueType::ListOf(box ref item) => json!({"type": "array", "items": export_type(item, additional_properties)}),
+        SchemaValueType::Map(ref m) => {
+            let mut required: Vec<&String> = m.iter()
+                .filter(|s| matches!(s.1, SchemaValueType::Required(_)))
+                .map(|s| s.0)
+                .collect();
+            required.sort();
+            let properties: Map<String, Value> = m.iter()
+                .map(|(k, s)| (k.clone(), Value::Object(export_type(s, additional_properties))))
+                .collect();
+            json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+                "additionalProperties": additional_properties,
+            })
+        }
+        SchemaValueType::Required(box ref inner) => return export_type(inner, additional_properties),
+        SchemaValueType::Constrained(box ref c) => {
+            let mut m = export_type(&c.value_type, additional_properties);
+            let keywords = [
+                ("minimum", c.minimum.as_ref().map(|n| json!(n))),
+                ("maximum", c.maximum.as_ref().map(|n| json!(n))),
+                ("minLength", c.min_length.map(|n| json!(n))),
+                ("maxLength", c.max_length.map(|n| json!(n))),
+                ("pattern", c.pattern.as_ref().map(|p| json!(p))),
+                ("enum", c.allowed.as_ref().map(|a| json!(a))),
+                ("minItems", c.min_items.map(|n| json!(n))),
+                ("maxItems", c.max_items.map(|n| json!(n))),
+            ];
+            for (k, v) in keywords.iter() {
+                if let Some(v) = v {
+                    m.insert((*k).to_owned(), v.clone());
+                }
+            }
+            return m;
+        }
+        SchemaValueType::OneOf(ref ls) => json!({
+            "anyOf": ls.iter().map(|s| export_type(s, additional_properties)).collect::<Vec<_>>(),
+        }),
+        SchemaValueType::Nullable(box ref inner) => json!({
+            "anyOf": [{"type": "null"}, export_type(inner, additional_properties)],
+        }),
+    };
+    match value {
+        Value::Object(m) => m,
+        _ => unreachable!(),
+    }
+}
+
+/// Schema equivalent to the JSON Schema document, named by its title, whose root must be an object schema.
+/// additionalProperties applies to the whole schema, so every object must agree on it
+pub fn import(doc: &Value) -> Result<Schema, ImportError> {
+    if let Some(dialect) = doc.get("$schema") {
+        if dialect != DIALECT {
+            return Err(ImportError::new("/$schema", format!("only {} is supported", DIALECT)));
+        }
+    }
+    if doc.get("type").and_then(Value::as_str) != Some("object") {
+        return Err(ImportError::new("", "root must be a schema of type object".to_owned()));
+    }
+    let mut importer = Importer { additional_properties: None };
+    let schema = match importer.import_type(doc, "")? {
+        SchemaValueType::Map(m) => m,
+        _ => return Err(ImportError::new("", "root must be a schema of type object without constraints".to_owned())),
+    };
+    check_schema(&schema).map_err(|e| ImportError::new("", e))?;
+    Ok(Schema {
+        name: doc.get("title").and_then(Value::as_str).map(str::to_owned),
+        schema: Some(schema),
+        additional_properties: Some(importer.additional_properties.unwrap_or(true)),
+        version: None,
+        id: None,
+    })
+}
+
+struct Importer {
+    additional_properties: Option<bool>,
+}
+
+impl Importer {
+    fn import_type(&mut self, v: &Value, path: &str) -> Result<SchemaValueType, ImportError> {
+        let m = v.as_object()
+            .ok_or_else(|| ImportError::new(path, "boolean schemas are not supported".to_owned()))?;
+        if let Some(k) = m.keys().find(|k| !KEYWORDS.contains(&k.as_str()) && !ANNOTATIONS.contains(&k.as_str())) {
+            return Err(ImportError::new(&pointer_push(path, k), format!("keyword {} is not supported", k)));
+        }
+        if let Some(any_of) = m.get("anyOf") {
+            if let Some(k) = m.keys().find(|k| k.as_str() != "anyOf" && KEYWORDS.contains(&k.as_str())) {
+                return Err(ImportError::new(&pointer_push(path, k), format!("keyword {} is not supported next to anyOf", k)));
+            }
+            let any_of_path = pointer_push(path, "anyOf");
+            let branches = any_of.as_array().filter(|a| !a.is_empty())
+                .ok_or_else(|| ImportError::new(&any_of_path, "anyOf must be a non-empty array".to_owned()))?;
+            let types = branches.iter().enumerate()
+                .map(|(n, b)| self.import_type(b, &pointer_push(&any_of_path, &n.to_string())))
+                .collect::<Result<Vec<_>, _>>()?;
+            return Ok(union(types));
+        }
+        let type_path = pointer_push(path, "type");
+        let names: Vec<&str> = match m.get("type") {
+            Some(Value::String(ref s)) => vec!(s.as_str()),
+            Some(Value::Array(ref a)) if !a.is_empty() => a.iter().map(Value::as_str).collect::<Option<_>>()
+                .ok_or_else(|| ImportError::new(&type_path, "type must be a string or an array of strings".to_owned()))?,
+            Some(_) => return Err(ImportError::new(&type_path, "type must be a string or an array of strings".to_owned())),
+            None => return Err(ImportError::new(path, "type is required".to_owned())),
+        };
+        let types = names.into_iter()
+            .map(|name| self.import_single(m, name, path))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(union(types))
+    }
+
+    /// Schema of one JSON type, keywords for other types are ignored as JSON Schema does
+    fn import_single(&mut self, m: &Map<String, Value>, name: &str, path: &str) -> Result<SchemaValueType, ImportError> {
+        let mut c = self.constraints(m, path)?;
+        let sbt = match name {
+            "boolean" => SchemaValueType::Bool,
+            "null" => SchemaValueType::Null,
+            // UInt exports as 0 to u64::MAX, a Constrained one raises the minimum
+            "integer" => {
+                if c.minimum.as_ref().map_or(false, |min| *min >= Bound(0.into()))
+                    && m.get("maximum").and_then(Value::as_u64) == Some(u64::MAX) {
+                    c.minimum = c.minimum.filter(|min| *min > Bound(0.into()));
+                    c.maximum = None;
+                    SchemaValueType::UInt
+                } else {
+                    SchemaValueType::Int
+                }
+            }
+            // JSON Schema numbers include integers, unlike Float
+            "number" => {
+                return Ok(SchemaValueType::OneOf(vec!(
+                    constrain(SchemaValueType::Int, c.clone()),
+                    constrain(SchemaValueType::Float, c),
+                )));
+            }
+            "string" => {
+                let semantic = match (m.get("format").map(|f| f.as_str()), m.get("contentEncoding").map(|e| e.as_str())) {
+                    (None, None) => SchemaValueType::String,
+                    (Some(Some("date")), None) => SchemaValueType::Date,
+                    (Some(Some("date-time")), None) => SchemaValueType::DateTime,
+                    (Some(Some("uri")), None) => SchemaValueType::Uri,
+                    (None, Some(Some("base64"))) => SchemaValueType::Bytes,
+                    (Some(_), None) => {
+                        return Err(ImportError::new(&pointer_push(path, "format"), "format must be one of date, date-time or uri".to_owned()));
+                    }
+                    (None, Some(_)) => {
+                        return Err(ImportError::new(&pointer_push(path, "contentEncoding"), "contentEncoding must be base64".to_owned()));
+                    }
+                    (Some(_), Some(_)) => {
+                        return Err(ImportError::new(path, "format and contentEncoding cannot be combined".to_owned()));
+                    }
+                };
+                if semantic != SchemaValueType::String
+                    && (c.min_length.is_some() || c.max_length.is_some() || c.pattern.is_some()) {
+                    return Err(ImportError::new(path, "minLength, maxLength and pattern are not supported with format or contentEncoding".to_owned()));
+                }
+                semantic
+            }
+            "array" => self.import_array(m, &mut c, path)?,
+            "object" => self.import_object(m, path)?,
+            _ => return Err(ImportError::new(&pointer_push(path, "type"), format!("type {} is not supported", name))),
+        };
+        Ok(constrain(sbt, c))
+    }
+
+    fn import_array(&mut self, m: &Map<String, Value>, c: &mut Constraints, path: &str) -> Result<SchemaValueType, ImportError> {
+        match (m.get("prefixItems"), m.get("items")) {
+            (Some(Value::Array(ref prefix)), Some(Value::Bool(false))) => {
+                if c.min_items != Some(prefix.len()) {
+                    return Err(ImportError::new(path, "prefixItems needs minItems equal to its length".to_owned()));
+                }
+                c.min_items = None;
+                c.max_items = None;
+                let prefix_path = pointer_push(path, "prefixItems");
+                Ok(SchemaValueType::List(prefix.iter().enumerate()
+                    .map(|(n, s)| self.import_type(s, &pointer_push(&prefix_path, &n.to_string())))
+                    .collect::<Result<_, _>>()?))
+            }
+            (Some(_), _) => Err(ImportError::new(path, "prefixItems needs to be an array with items set to false".to_owned())),
+            (None, Some(items)) => Ok(SchemaValueType::ListOf(Box::new(self.import_type(items, &pointer_push(path, "items"))?))),
+            (None, None) => Err(ImportError::new(path, "items is required for arrays".to_owned())),
+        }
+    }
+
+    fn import_object(&mut self, m: &Map<String, Value>, path: &str) -> Result<SchemaValueType, ImportError> {
+        let additional_properties = match m.get("additionalProperties") {
+            None => true,
+            Some(Value::Bool(b)) => *b,
+            Some(_) => {
+                return Err(ImportError::new(&pointer_push(path, "additionalProperties"), "additionalProperties must be a boolean".to_owned()));
+            }
+        };
+        if self.additional_properties.map_or(false, |a| a != additional_properties) {
+            return Err(ImportError::new(path, "additionalProperties must be the same on every object".to_owned()));
+        }
+        self.additional_properties = Some(additional_properties);
+
+        let properties_path = pointer_push(path, "properties");
+        let empty = Map::new();
+        let properties = match m.get("properties") {
+            None => &empty,
+            Some(Value::Object(ref p)) => p,
+            Some(_) => return Err(ImportError::new(&properties_path, "properties must be an object".to_owned())),
+        };
+        let required_path = pointer_push(path, "required");
+        let required: Vec<&str> = match m.get("required") {
+            None => vec!(),
+            Some(Value::Array(ref r)) => r.iter().map(Value::as_str).collect::<Option<_>>()
+                .ok_or_else(|| ImportError::new(&required_path, "required must be an array of strings".to_owned()))?,
+            Some(_) => return Err(ImportError::new(&required_path, "required must be an array of strings".to_owned())),
+        };
+        if let Some(k) = required.iter().find(|k| !properties.contains_key(**k)) {
+            return Err(ImportError::new(&required_path, format!("required key {} is not in properties", k)));
+        }
+        let mut sbt = HashMap::new();
+        for (k, s) in properties {
+            let t = self.import_type(s, &pointer_push(&properties_path, k))?;
+            sbt.insert(k.clone(), if required.contains(&k.as_str()) { SchemaValueType::Required(Box::new(t)) } else { t });
+        }
+        Ok(SchemaValueType::Map(sbt))
+    }
+
+    fn constraints(&self, m: &Map<String, Value>, path: &str) -> Result<Constraints, ImportError> {
+        let number = |k: &str| match m.get(k) {
+            None => Ok(None),
+            Some(Value::Number(n)) => Ok(Some(Bound(n.clone()))),
+            Some(_) => Err(ImportError::new(&pointer_push(path, k), format!("{} must be a number", k))),
+        };
+        let count = |k: &str| match m.get(k) {
+            None => Ok(None),
+            Some(v) => v.as_u64().map(|n| Some(n as usize))
+                .ok_or_else(|| ImportError::new(&pointer_push(path, k), format!("{} must be a non-negative integer", k))),
+        };
+        Ok(Constraints {
+            value_type: SchemaValueType::Null,
+            minimum: number("minimum")?,
+            maximum: number("maximum")?,
+            min_length: count("minLength")?,
+            max_length: count("maxLength")?,
+            pattern: match m.get("pattern") {
+                None => None,
+                Some(Value::String(ref p)) => Some(p.clone()),
+                Some(_) => return Err(ImportError::new(&pointer_push(path, "pattern"), "pattern must be a string".to_owned())),
+            },
+            allowed: match m.get("enum") {
+                None => None,
+                Some(Value::Array(ref a)) => Some(a.clone()),
+                Some(_) => return Err(ImportError::new(&pointer_push(path, "enum"), "enum must be an array".to_owned())),
+            },
+            min_items: count("minItems")?,
+            max_items: count("maxItems")?,
+            compiled_pattern: Default::default(),
+        })
+    }
+}
+
+/// Wraps the type with the constraints applying to it, if any
+fn constrain(sbt: SchemaValueType, c: Constraints) -> SchemaValueType {
+    let numeric = matches!(sbt, SchemaValueType::Int | SchemaValueType::UInt | SchemaValueType::Float);
+    let string = sbt == SchemaValueType::String;
+    let list = matches!(sbt, SchemaValueType::ListOf(_));
+    let c = Constraints {
+        minimum: c.minimum.filter(|_| numeric),
+        maximum: c.maximum.filter(|_| numeric),
+        min_length: c.min_length.filter(|_| string),
+        max_length: c.max_length.filter(|_| string),
+        pattern: c.pattern.filter(|_| string),
+        min_items: c.min_items.filter(|_| list),
+        max_items: c.max_items.filter(|_| list),
+        allowed: c.allowed,
+        value_type: sbt,
+        compiled_pattern: c.compiled_pattern,
+    };
+    if c.minimum.is_none() && c.maximum.is_none() && c.min_length.is_none() && c.max_length.is_none()
+        && c.pattern.is_none() && c.allowed.is_none() && c.min_items.is_none() && c.max_items.is_none() {
+        c.value_type
+    } else {
+        SchemaValueType::Constrained(Box::new(c))
+    }
+}
+
+/// One of the types, nested OneOfs are flattened and null alongside other types makes them Nullable
+fn union(types: Vec<SchemaValueType>) -> SchemaValueType {
+    let mut flat: Vec<SchemaValueType> = vec!();
+    for t in types {
+        let branches = match t {
+            SchemaValueType::OneOf(ls) => ls,
+            t => vec!(t),
+        };
+        for b in branches {
+            if !flat.contains(&b) {
+                flat.push(b);
+            }
+        }
+    }
+    let nullable = flat.len() > 1 && flat.contains(&SchemaValueType::Null);
+    if nullable {
+        flat.retain(|t| *t != SchemaValueType::Null);
+    }
+    let sbt = if flat.len() == 1 { flat.remove(0) } else { SchemaValueType::OneOf(flat) };
+    if nullable {
+        SchemaValueType::Nullable(Box::new(sbt))
+    } else {
+        sbt
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn schema(raw: &str, additional_properties: bool) -> Schema {
+        Schema {
+            name: Some("Birth certificate".to_owned()),
+            schema: Some(serde_json::from_str(raw).unwrap()),
+            additional_properties: Some(additional_properties),
+            version: None,
+            id: None,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let original = schema(r#"{
+  "name": {"Required": {"Constrained": {"type": "String", "min_length": 1, "pattern": "^[A-Z]"}}},
+  "middle_name": {"Nullable": "String"},
+  "birth_date": {"Required": "Date"},
+  "issued_at": {"Required": "DateTime"},
+  "photo": "Bytes",
+  "homepage": {"Required": "Uri"},
+  "siblings": {"Required": "UInt"},
+  "children": {"Required": {"Constrained": {"type": "UInt", "minimum": 5}}},
+  "country": {"Required": {"Constrained": {"type": "String", "enum": ["AU", "NZ"]}}},
+  "parents": {"Required": {"Constrained": {"type": {"ListOf": {"Map": {"name": {"Required": "String"}, "age": {"Required": {"OneOf": ["Int", "Float"]}}}}}, "max_items": 2}}},
+  "pair": {"Required": {"List": ["Bool", "Null"]}}
+}"#, false);
+        let exported = export(&original);
+        assert_eq!(exported["$schema"], DIALECT);
+        assert_eq!(exported["required"], json!(["birth_date", "children", "country", "homepage", "issued_at", "name", "pair", "parents", "siblings"]));
+        assert_eq!(exported["properties"]["birth_date"], json!({"type": "string", "format": "date"}));
+        assert_eq!(exported["properties"]["pair"]["minItems"], 2);
+        assert_eq!(import(&exported).unwrap(), original);
+    }
+
+    #[test]
+    fn import_json_schema() {
+        let imported = import(&json!({
+            "$schema": DIALECT,
+            "title": "Birth certificate",
+            "type": "object",
+            "properties": {
+                "name": {"type": "string", "description": "Full name"},
+                "height": {"type": "number", "minimum": 0},
+                "middle_name": {"type": ["string", "null"]},
+                "tags": {"type": "array", "items": {"type": "string"}}
+            },
+            "required": ["name", "height"]
+        })).unwrap();
+        let min = |t: SchemaValueType| SchemaValueType::Constrained(Box::new(Constraints {
+            value_type: t,
+            minimum: Some(Bound(0.into())),
+            maximum: None,
+            min_length: None,
+            max_length: None,
+            pattern: None,
+            allowed: None,
+            min_items: None,
+            max_items: None,
+            compiled_pattern: Default::default(),
+        }));
+        assert_eq!(imported.additional_properties, Some(true));
+        let sbt = imported.schema.unwrap();
+        assert_eq!(sbt["name"], SchemaValueType::Required(Box::new(SchemaValueType::String)));
+        assert_eq!(sbt["height"], SchemaValueType::Required(Box::new(
+            SchemaValueType::OneOf(vec!(min(SchemaValueType::Int), min(SchemaValueType::Float))))));
+        assert_eq!(sbt["middle_name"], SchemaValueType::Nullable(Box::new(SchemaValueType::String)));
+        assert_eq!(sbt["tags"], SchemaValueType::ListOf(Box::new(SchemaValueType::String)));
+    }
+
+    #[test]
+    fn import_rejects_unsupported() {
+        let error = |doc: Value| import(&doc).unwrap_err();
+        assert_eq!(error(json!({"type": "array", "items": {"type": "string"}})).path, "");
+        assert_eq!(error(json!({"$schema": "http://json-schema.org/draft-07/schema#", "type": "object"})).path, "/$schema");
+        assert_eq!(error(json!({"type": "object", "properties": {"a": {"type": "integer", "exclusiveMinimum": 0}}})),
+                   ImportError::new("/properties/a/exclusiveMinimum", "keyword exclusiveMinimum is not supported".to_owned()));
+        assert_eq!(error(json!({"type": "object", "properties": {"a/b": {"$ref": "#/$defs/x"}}})).path, "/properties/a~1b/$ref");
+        assert_eq!(error(json!({"type": "object", "properties": {"a": {"type": "string", "format": "email"}}})).path, "/properties/a/format");
+        assert_eq!(error(json!({"type": "object", "properties": {"a": {"type": "array"}}})).path, "/properties/a");
+        assert_eq!(error(json!({"type": "object", "required": ["a"]})).path, "/required");
+        assert_eq!(error(json!({"type": "object", "additionalProperties": false,
+                                "properties": {"a": {"type": "object"}}})).path, "/properties/a");
+        assert_eq!(error(json!({"type": "object", "properties": {"a": {"type": "string", "pattern": "("}}})).path, "");
+    }
+}
@@ -1,43 +1,123 @@
 #![feature(proc_macro_hygiene, decl_macro)]
 #![feature(box_patterns)]
+#![cfg_attr(test, feature(test))]
 
 #[macro_use]
 extern crate rocket;
+#[macro_use]
+extern crate log;
 
 mod routes;
 mod dao;
+mod crypto;
 mod datastructures;
+mod jcs;
+mod compatibility;
+mod jsonschema;
+mod database;
+mod storage;
 mod test;
 
-use rusqlite::Connection;
 use rocket::Rocket;
+use rocket::fairing::AdHoc;
+
+use crate::crypto::MasterKey;
+use crate::database::{optional, DatabaseConfig};
+use crate::storage::{Backend, Result as SR, Storage};
 
 pub struct Conf {
-    db_file_path: &'static str,
+    backend: Box<dyn Backend>,
+    /// Seals issuer private keys, issuance is disabled without one
+    master_key: Option<MasterKey>,
+    /// Whether updates and deletes without If-Match are refused with 428
+    require_if_match: bool,
 }
 
 impl Conf {
-    fn get_new_db_connection(&self) -> Connection {
-        Connection::open(self.db_file_path).unwrap()
+    /// The configured backend's storage, a pooled connection for SQLite, Err when the pool has none to hand out in time
+    fn get_storage(&self) -> SR<Box<dyn Storage + '_>> {
+        self.backend.storage()
     }
 }
 
+/// Reads the database settings, opens the backend and applies pending migrations,
+/// refusing to launch when the database is missing, newer than the binary or can not be migrated,
+/// or when require_if_match is not a boolean or issuer_master_key is not a base64 encoded 32 byte key
+#[allow(clippy::result_large_err)] // The signature of an attach fairing
+fn database_fairing(rocket: Rocket) -> Result<Rocket, Rocket> {
+    let require_if_match = match optional(rocket.config().get_bool("require_if_match")) {
+        Ok(r) => r.unwrap_or(true),
+        Err(e) => {
+            error!("{}", e);
+            return Err(rocket);
+        }
+    };
+    let master_key = match optional(rocket.config().get_str("issuer_master_key")) {
+        Ok(None) => None,
+        Ok(Some(k)) => match MasterKey::from_base64(k) {
+            Some(k) => Some(k),
+            None => {
+                error!("issuer_master_key must be a base64 encoded 32 byte key");
+                return Err(rocket);
+            }
+        },
+        Err(e) => {
+            error!("{}", e);
+            return Err(rocket);
+        }
+    };
+    let opened = DatabaseConfig::from_config(rocket.config())
+        .and_then(|d| storage::open(&d, false).map(|(b, m)| (d, b, m)));
+    let (database, backend) = match opened {
+        Ok((d, b, applied)) => {
+            for m in applied {
+                info!("applied migration {}", m.name);
+            }
+            (d, b)
+        }
+        Err(e) => {
+            error!("{}", e);
+            return Err(rocket);
+        }
+    };
+    info!("{:?} database {} (journal mode {}, busy timeout {}ms, pool size {}, read only {})",
+          database.backend, database.path.display(), database.journal_mode.as_deref().unwrap_or("unchanged"),
+          database.busy_timeout.as_millis(), database.pool_size, database.read_only);
+    Ok(rocket.manage(Conf { backend, master_key, require_if_match }))
+}
+
 fn get_ignited_rocket() -> Rocket {
-    rocket::ignite()
-        .manage(Conf { db_file_path: "db" })
+    setup_rocket(rocket::ignite())
+}
+
+/// Reads the database settings, issuer_master_key and require_if_match from Rocket config (Rocket.toml or ROCKET_*) and mounts all routes
+fn setup_rocket(rocket: Rocket) -> Rocket {
+    rocket
+        .attach(AdHoc::on_attach("Database", database_fairing))
         .mount("/credentials", routes![
             routes::credentials::get_credentials,
             routes::credentials::get_credential_by_id,
+            routes::credentials::get_credential_signing_input,
             routes::credentials::create_credential,
+            routes::credentials::issue_credential,
             routes::credentials::delete_credential,
             routes::credentials::update_credential,
+            routes::credentials::replace_credential,
+            routes::credentials::patch_credential,
             ])
         .mount("/schemas", routes![
             routes::schemas::get_schemas,
             routes::schemas::get_schema_by_id,
+            routes::schemas::get_schema_versions,
+            routes::schemas::get_schema_version,
+            routes::schemas::get_schema_json_schema,
+            routes::schemas::check_schema_compatibility,
+            routes::schemas::import_json_schema,
             routes::schemas::create_schema,
             routes::schemas::delete_schema,
             routes::schemas::update_schema,
+            routes::schemas::replace_schema,
+            routes::schemas::patch_schema,
         ])
         .mount("/cryptographic_keys", routes![
             routes::cryptographic_keys::get_cryptographic_keys,
@@ -45,9 +125,37 @@ fn get_ignited_rocket() -> Rocket {
             routes::cryptographic_keys::create_cryptographic_key,
             routes::cryptographic_keys::delete_cryptographic_key,
             routes::cryptographic_keys::update_cryptographic_key,
+            routes::cryptographic_keys::replace_cryptographic_key,
+            routes::cryptographic_keys::patch_cryptographic_key,
+        ])
+        .mount("/issuers", routes![
+            routes::issuers::get_issuers,
+            routes::issuers::get_issuer_by_id,
+            routes::issuers::create_issuer,
+            routes::issuers::delete_issuer,
+        ])
+        .mount("/verify", routes![
+            routes::verify::verify,
         ])
 }
 
+/// With --migrate-only, creates or migrates the configured database and exits instead of launching
 fn main() {
+    if std::env::args().skip(1).any(|arg| arg == "--migrate-only") {
+        let rocket = rocket::ignite();
+        match DatabaseConfig::from_config(rocket.config()).and_then(|d| storage::open(&d, true).map(|(_, m)| (d, m))) {
+            Ok((d, applied)) => {
+                for m in applied.iter() {
+                    println!("applied migration {}", m.name);
+                }
+                println!("database {} is up to date", d.path.display());
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
         get_ignited_rocket().launch();
+    }
 }
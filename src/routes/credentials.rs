@@ -1,124 +1,261 @@
 use std::io::Cursor;
 
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use rocket::{Response, State};
-use rocket::http::Status;
+use rocket::http::{ContentType, Status};
 use rocket_contrib::json::Json;
+use serde_json::Value;
 
 use crate::Conf;
-use crate::dao::ConnectionRestMapping;
-use crate::datastructures::{conforms, Credential, CryptographicKeys, ErrorMessage, Schema};
+use crate::crypto::{signer_for, signing_input, verify};
+use crate::datastructures::{Credential, CredentialError, CredentialFilter, CryptographicKeys, DetailedErrorMessage, ErrorMessage, IssueRequest, SigningInput, VerificationReport};
+use crate::storage::{Error, Resource, Result as SR, Storage};
 
 use super::internal::*;
+use super::issuers::{issuer_public_key_id, no_master_key_response};
 
-// Dummy function in place of verification
-fn verify(_cred: &Credential, _key: &CryptographicKeys) -> bool {
-    // Compare (fingerprint) == f(data + public key)
-    true
+/// schema_id and public_key_id filter the credentials
+#[get("/?<limit>&<offset>&<sort>&<fields>&<schema_id>&<public_key_id>")]
+pub fn get_credentials(state: State<Conf>, limit: Option<u32>, offset: Option<u32>, sort: Option<String>, fields: Option<String>,
+                       schema_id: Option<u64>, public_key_id: Option<u64>) -> Response<'static> {
+    let filter = CredentialFilter { schema_id, public_key_id };
+    generic_get(state, limit, offset, sort, fields, Box::new(move |s, page| s.get_credentials(filter, page)))
 }
 
-#[get("/?<limit>&<offset>")]
-pub fn get_credentials(state: State<Conf>, limit: Option<u32>, offset: Option<u32>) -> Response {
-    generic_get(state, limit, offset, Box::new(Credential::get_all))
+#[get("/<id>")]
+pub fn get_credential_by_id(state: State<Conf>, id: u64) -> Response {
+    generic_get_by_id(state, id, Some(Resource::Credentials), Box::new(|s, id| s.get_credential(id)))
 }
 
-#[get("/<id>")]
-pub fn get_credential_by_id(state: State<Conf>, id: u32) -> Response {
-    generic_get_by_id(state, id, Box::new(Credential::get_by_id))
+/// The canonical bytes the stored credential's finger_print must sign, to help issuers debug signatures
+#[get("/<id>/signing_input")]
+pub fn get_credential_signing_input(state: State<Conf>, id: u64) -> Response {
+    generic_get_by_id(state, id, None, Box::new(|s, id| s.get_credential(id).map(|c| SigningInput {
+        id: c.id,
+        signing_input: signing_input(&c),
+    })))
 }
 
-/// Check the object to ensure conforms to schema and has keys
-/// returns Some(Response) when check fails which shall be returned
-pub fn check_request_credential<'a>(state: &'a State<Conf>, cd: &Json<Credential>) -> Option<Response<'static>> {
-    let conn = state.get_new_db_connection();
-    let mut response = json_response();
-    // Check for schema
-    if cd.schema_id.is_none() {
-        response.sized_body(
-            Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid schema_id field" }).unwrap()))
-            .status(Status::BadRequest);
-        return Some(response.finalize());
-    }
-    let schema_id = cd.schema_id.unwrap();
-    // Check is data conforms to data
-    match Schema::get_by_id(&conn, schema_id) {
-        Ok(Some(ref schema)) => {
-            if schema.schema.is_none() || !conforms(&cd.0, &schema.schema.clone().unwrap()) {
-                return Some(response.sized_body(
-                    Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid or Nonconforming schema" }).unwrap()))
-                    .status(Status::BadRequest).finalize());
+/// Runs every check on a credential without writing anything: the data conforms to its schema and
+/// finger_print is signed by a valid key, either inline_key or the stored key referenced by public_key_id.
+/// Only stored keys are checked for revocation and expiry, those of inline_key are ignored
+pub fn verify_credential(storage: &dyn Storage, cd: &Credential, inline_key: Option<&CryptographicKeys>) -> VerificationReport {
+    let mut report = VerificationReport::default();
+    check_conformance(storage, cd, &mut report);
+
+    // Check finger_print is signed by the key
+    let stored_key;
+    let key = match (inline_key, cd.public_key_id) {
+        (Some(k), _) => {
+            report.key_inline = true;
+            Some(k)
+        }
+        (None, None) => {
+            report.errors.push(CredentialError::MissingPublicKeyId);
+            None
+        }
+        (None, Some(id)) => {
+            stored_key = storage.get_cryptographic_key(id);
+            match stored_key {
+                Ok(ref k) => Some(k),
+                _ => {
+                    report.errors.push(CredentialError::KeyNotFound);
+                    None
+                }
             }
         }
-        _ => {
-            return Some(response.sized_body(
-                Cursor::new(serde_json::to_string(&ErrorMessage { error: "No schema found" }).unwrap()))
-                .status(Status::BadRequest).finalize());
+    };
+    if let Some(k) = key {
+        if !report.key_inline {
+            report.key_found = true;
+            report.key_revoked = k.is_revoked();
+            report.key_expires_at = k.expires_at;
+            report.key_expired = k.is_expired();
+        }
+        if report.key_revoked {
+            report.errors.push(CredentialError::KeyRevoked);
+        }
+        if report.key_expired {
+            report.errors.push(CredentialError::KeyExpired);
+        }
+        // Signed over the schema version the data is checked against, the latest when the credential is not pinned
+        match verify(&Credential { schema_version: report.schema_version, ..cd.clone() }, k) {
+            Ok(()) => report.signature_valid = true,
+            Err(e) => report.errors.push(CredentialError::Signature(e)),
         }
     }
-    None
+
+    report.valid = report.errors.is_empty();
+    report
+}
+
+/// Check is data conforms to schema, at the pinned version if any
+fn check_conformance(storage: &dyn Storage, cd: &Credential, report: &mut VerificationReport) {
+    let schema = cd.schema_id.map(|id| match cd.schema_version {
+        Some(version) => storage.get_schema_version(id, version),
+        None => storage.get_schema(id),
+    });
+    match schema {
+        None => report.errors.push(CredentialError::MissingSchemaId),
+        Some(Ok(ref schema)) => {
+            report.schema_found = true;
+            report.schema_version = schema.version;
+            report.schema_violations = schema.validate(cd);
+            report.schema_conforms = schema.schema.is_some() && report.schema_violations.is_empty();
+            if !report.schema_conforms {
+                report.errors.push(CredentialError::Nonconforming);
+            }
+        }
+        _ => report.errors.push(CredentialError::SchemaNotFound),
+    }
+}
+
+/// Check the object to ensure conforms to schema and is signed by a valid key, pinning it to the schema version checked
+/// returns Some(Response) when check fails which shall be returned
+pub fn check_request_credential(storage: &dyn Storage, cd: &mut Credential) -> Option<Response<'static>> {
+    let report = verify_credential(storage, cd, None);
+    cd.schema_version = report.schema_version;
+    rejection_response(&report)
+}
+
+/// 400 response for the first error of the report, None when there is none
+fn rejection_response(report: &VerificationReport) -> Option<Response<'static>> {
+    let body = match report.errors.first()? {
+        CredentialError::Signature(e) => serde_json::to_string(
+            &DetailedErrorMessage { error: "Signature verification failed", details: e.message() }).unwrap(),
+        e @ CredentialError::Nonconforming => serde_json::to_string(
+            &DetailedErrorMessage { error: e.message(), details: &report.schema_violations }).unwrap(),
+        e => serde_json::to_string(&ErrorMessage { error: e.message() }).unwrap(),
+    };
+    Some(json_response().sized_body(Cursor::new(body)).status(Status::BadRequest).finalize())
 }
 
 #[post("/", data = "<cd>")]
 pub fn create_credential(state: State<Conf>, cd: Json<Credential>) -> Response {
-    if let Some(res) = check_request_credential(&state, &cd) {
-        return res;
-    }
-    generic_create(state, cd, Box::new(Credential::create))
+    generic_create_checked(state, cd, Box::new(check_request_credential), Box::new(|s, data| s.create_credential(data)))
 }
 
-#[put("/", data = "<cd>")]
-pub fn update_credential(state: State<Conf>, cd: Json<Credential>) -> Response {
-    let conn = state.get_new_db_connection();
+/// Signs the data with the issuer's key and stores the credential,
+/// the issuer's public key is stored in cryptographic_keys if it is not already
+#[post("/issue", data = "<req>")]
+pub fn issue_credential(state: State<Conf>, req: Json<IssueRequest>) -> Response {
+    let master_key = match state.inner().master_key {
+        Some(ref k) => k,
+        None => return no_master_key_response(),
+    };
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
     let mut response = json_response();
-    // Check for schema
-    if cd.schema_id.is_none() {
-        response.sized_body(
-            Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid schema_id field" }).unwrap()))
-            .status(Status::BadRequest);
-        return response.finalize();
-    }
-    let schema_id = cd.schema_id.unwrap();
-    // Check is data conforms to data
-    match Schema::get_by_id(&conn, schema_id) {
-        Ok(Some(ref schema)) => {
-            if schema.schema.is_none() || !conforms(&cd.0, &schema.schema.clone().unwrap()) {
-                return response.sized_body(
-                    Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid or Nonconforming schema" }).unwrap()))
-                    .status(Status::BadRequest).finalize();
-            }
-        }
+    let issuer = match req.issuer_id.map(|id| storage.get_issuer(id)) {
+        Some(Ok(i)) => i,
         _ => {
             return response.sized_body(
-                Cursor::new(serde_json::to_string(&ErrorMessage { error: "No schema found" }).unwrap()))
+                Cursor::new(serde_json::to_string(&ErrorMessage { error: "No issuer found" }).unwrap()))
                 .status(Status::BadRequest).finalize();
         }
+    };
+    let private_key = match issuer.private_key.as_ref().and_then(|k| master_key.open(k)) {
+        Some(k) => k,
+        None => {
+            return response.sized_body(
+                Cursor::new(serde_json::to_string(&ErrorMessage { error: "Issuer private key can not be decrypted" }).unwrap()))
+                .status(Status::InternalServerError).finalize();
+        }
+    };
+    let mut cd = Credential {
+        id: None,
+        schema_id: req.schema_id,
+        schema_version: None,
+        public_key_id: None,
+        finger_print: None,
+        data: req.data.clone(),
+    };
+
+    // One unit of work, so that concurrent issuances store the issuer's key once and a rejected one stores nothing
+    let mut rejected = None;
+    let mut id = 0;
+    let res = storage.transaction(&mut |s| {
+        // The data must conform before the issuer's key is stored
+        let mut report = VerificationReport::default();
+        check_conformance(s, &cd, &mut report);
+        rejected = rejection_response(&report);
+        if rejected.is_some() {
+            return Ok(());
+        }
+        cd.schema_version = report.schema_version;
+        // Read again, another issuance may have stored the key since
+        let issuer = s.get_issuer(issuer.id.ok_or(Error::NotFound)?)?;
+        cd.public_key_id = match issuer_public_key_id(s, &issuer, &private_key)? {
+            Some(id) => Some(id),
+            None => return Err(Error::Backend("issuer public key can not be derived".to_owned())),
+        };
+        cd.finger_print = signer_for(issuer.algorithm.unwrap_or_default())
+            .sign(&private_key, signing_input(&cd).as_bytes())
+            .map(|s| BASE64.encode(s));
+        rejected = check_request_credential(s, &mut cd);
+        if rejected.is_some() {
+            // Discards the key stored above
+            return Err(Error::Constraint("issued credential rejected".to_owned()));
+        }
+        id = s.create_credential(&cd)?;
+        Ok(())
+    });
+    match rejected {
+        Some(response) => response,
+        None => create_response(&cd, res.map(|()| id)),
     }
+}
 
-    if cd.public_key_id.is_none() {
-        return response.sized_body(
-            Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid public_id field" }).unwrap()))
-            .status(Status::BadRequest).finalize();
+/// Only data and finger_print can change, the rest is taken from the stored credential when left out,
+/// the result is checked as on creation, against the schema version the credential is pinned to
+fn check_changed_credential(storage: &dyn Storage, stored: &Credential, cd: &mut Credential) -> SR<Option<Response<'static>>> {
+    cd.schema_id = cd.schema_id.or(stored.schema_id);
+    cd.schema_version = cd.schema_version.or(stored.schema_version);
+    cd.public_key_id = cd.public_key_id.or(stored.public_key_id);
+    if (cd.schema_id, cd.schema_version, cd.public_key_id) != (stored.schema_id, stored.schema_version, stored.public_key_id) {
+        return Ok(Some(unprocessable_response("Invalid request body", "only data and finger_print can change")));
     }
-    let public_key_id = cd.public_key_id.unwrap();
-    match CryptographicKeys::get_by_id(&conn, public_key_id) {
-        Ok(Some(ref k)) => {
-            if k.public_key.is_none() || verify(&cd, k) {
-                response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid or Nonconforming schema" }).unwrap()))
-                    .status(Status::BadRequest).finalize();
-            }
-        }
+    Ok(check_request_credential(storage, cd))
+}
 
-        _ => {
-            return response.sized_body(
-                Cursor::new(serde_json::to_string(&ErrorMessage { error: "No key found" }).unwrap()))
-                .status(Status::BadRequest).finalize();
-        }
+fn change_credential(state: State<Conf>, if_match: IfMatch, id: u64, change: Change) -> Response<'static> {
+    generic_change(state, if_match, id, change,
+                   Box::new(|s, id| s.get_credential(id)),
+                   Box::new(check_changed_credential),
+                   Box::new(|s, data| s.update_credential(data)))
+}
+
+/// Same as PUT /credentials/<id> with the id in the body
+#[put("/", data = "<cd>")]
+pub fn update_credential(state: State<Conf>, if_match: IfMatch, cd: Json<Value>) -> Response<'static> {
+    match cd.get("id").and_then(Value::as_u64) {
+        Some(id) => change_credential(state, if_match, id, Change::Replace(cd.into_inner())),
+        None => json_response()
+            .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid request body" }).unwrap()))
+            .status(Status::UnprocessableEntity)
+            .finalize(),
     }
+}
 
-    generic_create(state, cd, Box::new(Credential::create))
+#[put("/<id>", data = "<cd>")]
+pub fn replace_credential(state: State<Conf>, if_match: IfMatch, id: u64, cd: Json<Value>) -> Response<'static> {
+    change_credential(state, if_match, id, Change::Replace(cd.into_inner()))
+}
+
+/// The patched data has to be signed by a patched finger_print
+#[patch("/<id>", data = "<patch>")]
+pub fn patch_credential(state: State<Conf>, if_match: IfMatch, content_type: Option<&ContentType>, id: u64, patch: Json<Value>) -> Response<'static> {
+    match Change::patch(content_type, patch.into_inner()) {
+        Some(change) => change_credential(state, if_match, id, change),
+        None => unsupported_patch_response(),
+    }
 }
 
 #[delete("/<id>")]
-pub fn delete_credential(state: State<Conf>, id: u32) -> Response {
-    return generic_delete::<Credential>(state, id, Box::new(Credential::delete_by_id));
+pub fn delete_credential(state: State<Conf>, if_match: IfMatch, id: u64) -> Response {
+    return generic_delete::<Credential>(state, if_match, id, Box::new(|s, id| s.delete_credential(id)));
 }
 
@@ -3,48 +3,113 @@ use std::io::Cursor;
 use rocket::{Response, State};
 use rocket::http::{Status, ContentType};
 use rocket_contrib::json::Json;
+use serde_json::Value;
 
 use crate::Conf;
-use crate::dao::ConnectionRestMapping;
-use crate::datastructures::{CryptographicKeys, ErrorMessage};
+use crate::crypto::{check_public_key, VerificationError};
+use crate::datastructures::{CryptographicKeys, DetailedErrorMessage, ErrorMessage, KeyFilter, SignatureAlgorithm};
+use crate::storage::{Error, Resource, Result as SR, Storage};
 
 use super::internal::*;
 
-#[get("/?<limit>&<offset>")]
-pub fn get_cryptographic_keys(state: State<Conf>, limit: Option<u32>, offset: Option<u32>) -> Response {
-    return generic_get(state, limit, offset, Box::new( CryptographicKeys::get_all));
+/// algorithm and revoked filter the keys
+#[get("/?<limit>&<offset>&<sort>&<fields>&<algorithm>&<revoked>")]
+pub fn get_cryptographic_keys(state: State<Conf>, limit: Option<u32>, offset: Option<u32>, sort: Option<String>, fields: Option<String>,
+                              algorithm: Option<String>, revoked: Option<bool>) -> Response<'static> {
+    let algorithm = match algorithm.as_ref().map(|a| SignatureAlgorithm::from_name(a)) {
+        None => None,
+        Some(Some(a)) => Some(a),
+        Some(None) => return invalid_query_response("algorithm is not supported"),
+    };
+    let filter = KeyFilter { algorithm, revoked };
+    generic_get(state, limit, offset, sort, fields, Box::new(move |s, page| s.get_cryptographic_keys(filter, page)))
 }
 
 #[get("/<id>")]
-pub fn get_cryptographic_key_by_id(state: State<Conf>, id: u32) -> Response {
-    return generic_get_by_id(state, id, Box::new(CryptographicKeys::get_by_id));
+pub fn get_cryptographic_key_by_id(state: State<Conf>, id: u64) -> Response {
+    generic_get_by_id(state, id, Some(Resource::CryptographicKeys), Box::new(|s, id| s.get_cryptographic_key(id)))
+}
+
+fn invalid_key_response(e: VerificationError) -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&DetailedErrorMessage { error: "Invalid public_key field", details: e.message() }).unwrap()))
+        .status(Status::BadRequest)
+        .finalize()
 }
 
 #[post("/", data = "<ck>")]
-pub fn create_cryptographic_key(state: State<Conf>, ck: Json<CryptographicKeys>) -> Response {
+pub fn create_cryptographic_key(state: State<Conf>, mut ck: Json<CryptographicKeys>) -> Response {
     if ck.public_key.is_none() {
         let mut response = Response::build();
         response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid public_key field" }).unwrap()))
             .status(Status::InternalServerError);
         response.header(ContentType::JSON).finalize()
+    } else if let Err(e) = check_public_key(&ck) {
+        invalid_key_response(e)
     } else {
-        generic_create(state, ck, Box::new(CryptographicKeys::create))
+        ck.algorithm = Some(ck.algorithm.unwrap_or_default());
+        generic_create(state, ck, Box::new(|s, data| s.create_cryptographic_key(data)))
     }
 }
 
+/// The key must have a valid public_key, the algorithm defaults as on creation.
+/// Credentials verify with the key, so while any reference it only revoked and expires_at can change
+fn check_changed_key(storage: &dyn Storage, stored: &CryptographicKeys, ck: &mut CryptographicKeys) -> SR<Option<Response<'static>>> {
+    if ck.public_key.is_none() {
+        return Ok(Some(unprocessable_response("Invalid request body", "public_key is required")));
+    }
+    if let Err(e) = check_public_key(ck) {
+        return Ok(Some(invalid_key_response(e)));
+    }
+    ck.algorithm = Some(ck.algorithm.unwrap_or_default());
+    if (&ck.public_key, ck.algorithm) != (&stored.public_key, Some(stored.algorithm.unwrap_or_default())) {
+        let credential_ids = storage.credential_ids_by_public_key_id(stored.id.ok_or(Error::NotFound)?)?;
+        if !credential_ids.is_empty() {
+            return Ok(Some(referenced_response(credential_ids)));
+        }
+    }
+    Ok(None)
+}
+
+fn change_cryptographic_key(state: State<Conf>, if_match: IfMatch, id: u64, change: Change) -> Response<'static> {
+    generic_change(state, if_match, id, change,
+                   Box::new(|s, id| s.get_cryptographic_key(id)),
+                   Box::new(check_changed_key),
+                   Box::new(|s, data| s.update_cryptographic_key(data)))
+}
+
+/// Same as PUT /cryptographic_keys/<id> with the id in the body
 #[put("/", data = "<ck>")]
-pub fn update_cryptographic_key(state: State<Conf>, ck: Json<CryptographicKeys>) -> Response {
-    if ck.public_key.is_some() && ck.id.is_some() {
-        generic_update(state, ck, Box::new(CryptographicKeys::update))
-    } else {
-        let mut response = Response::build();
-        response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid request body" }).unwrap()))
-            .status(Status::UnprocessableEntity);
-        response.header(ContentType::JSON).finalize()
+pub fn update_cryptographic_key(state: State<Conf>, if_match: IfMatch, ck: Json<Value>) -> Response {
+    match ck.get("id").and_then(Value::as_u64) {
+        Some(id) => change_cryptographic_key(state, if_match, id, Change::Replace(ck.into_inner())),
+        None => {
+            let mut response = Response::build();
+            response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid request body" }).unwrap()))
+                .status(Status::UnprocessableEntity);
+            response.header(ContentType::JSON).finalize()
+        }
+    }
+}
+
+#[put("/<id>", data = "<ck>")]
+pub fn replace_cryptographic_key(state: State<Conf>, if_match: IfMatch, id: u64, ck: Json<Value>) -> Response {
+    change_cryptographic_key(state, if_match, id, Change::Replace(ck.into_inner()))
+}
+
+#[patch("/<id>", data = "<patch>")]
+pub fn patch_cryptographic_key(state: State<Conf>, if_match: IfMatch, content_type: Option<&ContentType>, id: u64, patch: Json<Value>) -> Response<'static> {
+    match Change::patch(content_type, patch.into_inner()) {
+        Some(change) => change_cryptographic_key(state, if_match, id, change),
+        None => unsupported_patch_response(),
     }
 }
 
-#[delete("/<id>")]
-pub fn delete_cryptographic_key(state: State<Conf>, id: u32) -> Response {
-    return generic_delete::<CryptographicKeys>(state, id, Box::new(CryptographicKeys::delete_by_id));
+/// on_delete is restrict or cascade, deciding what happens to credentials signed with the key
+#[delete("/<id>?<on_delete>")]
+pub fn delete_cryptographic_key(state: State<Conf>, if_match: IfMatch, id: u64, on_delete: Option<String>) -> Response {
+    generic_delete_referenced::<CryptographicKeys>(state, if_match, id, on_delete,
+                                                   Box::new(|s, id| s.credential_ids_by_public_key_id(id)),
+                                                   Box::new(|s, id| s.delete_credentials_by_public_key_id(id)),
+                                                   Box::new(|s, id| s.delete_cryptographic_key(id)))
 }
@@ -1,24 +1,56 @@
 use std::io::Cursor;
 
-use rocket::{Response, State};
+use rocket::{Request, Response, State};
 use rocket::http::{ContentType, Status};
+use rocket::request::{self, FromRequest};
 use rocket::response::ResponseBuilder;
+use rocket::Outcome;
 use rocket_contrib::json::Json;
-use rusqlite::{Connection, Error, Result as SR};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
 
 use crate::Conf;
-use crate::datastructures::{ErrorMessage, IdObj, ProjectData};
+use crate::storage::{Error, Resource, Result as SR, Storage};
+use crate::datastructures::{Dependents, DetailedErrorMessage, ErrorMessage, IdObj, OnDelete, Page, ProjectData, Sort};
 
+type GetAllMapping<T> = Box<dyn Fn(&dyn Storage, Page) -> SR<Vec<T>>>;
+type GetByIdMapping<T> = Box<dyn Fn(&dyn Storage, u64) -> SR<T>>;
+type CreateMapping<T> = Box<dyn Fn(&dyn Storage, &T) -> SR<u64>>;
+type UpdateMapping<T> = Box<dyn Fn(&dyn Storage, &T) -> SR<()>>;
+type DeleteMapping = Box<dyn Fn(&dyn Storage, u64) -> SR<()>>;
+type DependentsMapping = Box<dyn Fn(&dyn Storage, u64) -> SR<Vec<u64>>>;
+/// Some(Response) rejects the object with that response, the object may be amended before it is written
+type CheckMapping<T> = Box<dyn Fn(&dyn Storage, &mut T) -> Option<Response<'static>>>;
+/// CheckMapping of a changed object, given the stored one it replaces, Err when what it reads can not be read
+type ChangeCheckMapping<T> = Box<dyn Fn(&dyn Storage, &T, &mut T) -> SR<Option<Response<'static>>>>;
+
+/// Lists objects sorted by id, or by descending id when sort is -id.
+/// fields is a comma separated list of the fields to respond with, all when None
 pub fn generic_get<'a, T: ProjectData<'a>>(state: State<Conf>,
                                            limit: Option<u32>,
                                            offset: Option<u32>,
-                                           mapping: Box<dyn Fn(&Connection, Option<u32>, Option<u32>) -> SR<Vec<T>>>,
+                                           sort: Option<String>,
+                                           fields: Option<String>,
+                                           mapping: GetAllMapping<T>,
 ) -> Response<'static> {
-    let conn = state.get_new_db_connection();
+    let sort = match sort.as_ref().map(|s| Sort::from_name(s)) {
+        None => Sort::default(),
+        Some(Some(s)) => s,
+        Some(None) => return invalid_query_response("sort must be id or -id"),
+    };
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
     let mut response = json_response();
-    match mapping(&conn, limit, offset) {
+    match mapping(&*storage, Page { limit, offset, sort }) {
         Ok(vec) => {
-            response.sized_body(Cursor::new(serde_json::to_string(&vec).unwrap()))
+            let body = match fields {
+                Some(fields) => serde_json::to_string(&project(&vec, &fields)),
+                None => serde_json::to_string(&vec),
+            };
+            response.sized_body(Cursor::new(body.unwrap()))
                 .status(Status::Ok);
         }
         _ => {
@@ -29,19 +61,100 @@ pub fn generic_get<'a, T: ProjectData<'a>>(state: State<Conf>,
     response.finalize()
 }
 
+/// The objects with only the comma separated fields, fields they do not have are left out
+fn project<T: Serialize>(objects: &[T], fields: &str) -> Vec<Value> {
+    let fields: Vec<&str> = fields.split(',').map(str::trim).collect();
+    objects.iter().map(|o| match serde_json::to_value(o).unwrap() {
+        Value::Object(mut object) => {
+            object.retain(|k, _| fields.contains(&k.as_str()));
+            Value::Object(object)
+        }
+        v => v,
+    }).collect()
+}
+
+/// 503 when no storage can be had for the request, e.g. every pooled connection stayed in use
+pub fn unavailable_response() -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Storage unavailable" }).unwrap()))
+        .status(Status::ServiceUnavailable)
+        .finalize()
+}
+
+/// 400 response for a query parameter with a value that is not understood
+pub fn invalid_query_response(error: &'static str) -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error }).unwrap()))
+        .status(Status::BadRequest)
+        .finalize()
+}
+
 
-pub fn generic_get_by_id<'a, T: ProjectData<'a>>(state: State<Conf>,
-                                                 id: u32,
-                                                 mapping: Box<dyn Fn(&Connection, u32) -> SR<Option<T>>>,
+/// The If-Match header of the request, the ETags the client expects the object to still have
+pub struct IfMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(IfMatch(request.headers().get_one("If-Match").map(str::to_owned)))
+    }
+}
+
+impl IfMatch {
+    /// Some(Response) when the write must not go ahead: 428 when the header is missing and required,
+    /// 412 when it lists neither * nor the current ETag of the object. Err(NotFound) when there is no object
+    pub fn check(&self, storage: &dyn Storage, required: bool, resource: Resource, id: u64) -> SR<Option<Response<'static>>> {
+        let etags = match self.0 {
+            Some(ref etags) => etags,
+            None if required => {
+                return Ok(Some(json_response()
+                    .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "If-Match header required" }).unwrap()))
+                    .status(Status::PreconditionRequired)
+                    .finalize()));
+            }
+            None => return Ok(None),
+        };
+        let current = etag(storage.revision(resource, id)?);
+        if etags.split(',').map(str::trim).any(|e| e == "*" || e == current) {
+            return Ok(None);
+        }
+        Ok(Some(json_response()
+            .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Object has been modified" }).unwrap()))
+            .raw_header("ETag", current)
+            .status(Status::PreconditionFailed)
+            .finalize()))
+    }
+}
+
+/// The strong ETag of a revision
+pub fn etag(revision: u64) -> String {
+    format!("\"{}\"", revision)
+}
+
+/// Responds with the ETag of the object when etag is set, a projection of an object has none
+pub fn generic_get_by_id<T: Serialize>(state: State<Conf>,
+                                       id: u64,
+                                       etag: Option<Resource>,
+                                       mapping: GetByIdMapping<T>,
 ) -> Response<'static> {
-    let conn = state.get_new_db_connection();
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
     let mut response = json_response();
-    match mapping(&conn, id) {
-        Ok(Some(ref s)) => {
+    // The revision is read first, so that the ETag is never newer than the object
+    let res = etag.map(|r| storage.revision(r, id)).transpose()
+        .and_then(|revision| Ok((revision, mapping(&*storage, id)?)));
+    match res {
+        Ok((revision, ref s)) => {
+            if let Some(revision) = revision {
+                response.raw_header("ETag", self::etag(revision));
+            }
             response.sized_body(Cursor::new(serde_json::to_string(s).unwrap()))
                 .status(Status::Ok);
         }
-        Err(Error::QueryReturnedNoRows) => {
+        Err(Error::NotFound) => {
             response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Id not found" }).unwrap()))
                 .status(Status::NotFound);
         }
@@ -55,11 +168,44 @@ pub fn generic_get_by_id<'a, T: ProjectData<'a>>(state: State<Conf>,
 
 pub fn generic_create<'a, T: ProjectData<'a>>(state: State<Conf>,
                                               data: Json<T>,
-                                              mapping: Box<dyn Fn(&Connection, &T) -> SR<u32>>,
+                                              mapping: CreateMapping<T>,
+) -> Response {
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
+    let res = mapping(&*storage, &data);
+    create_response(&data.0, res)
+}
+
+/// generic_create with check run in the same unit of work as the write, so that nothing it read changes before the write
+pub fn generic_create_checked<'a, T: ProjectData<'a>>(state: State<Conf>,
+                                                      mut data: Json<T>,
+                                                      check: CheckMapping<T>,
+                                                      mapping: CreateMapping<T>,
 ) -> Response {
-    let conn = state.get_new_db_connection();
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
+    let mut rejected = None;
+    let mut id = 0;
+    let res = storage.transaction(&mut |s| {
+        rejected = check(s, &mut data.0);
+        if rejected.is_none() {
+            id = mapping(s, &data)?;
+        }
+        Ok(())
+    });
+    match rejected {
+        Some(response) => response,
+        None => create_response(&data.0, res.map(|()| id)),
+    }
+}
+
+pub fn create_response<'a, 'r, T: ProjectData<'a>>(data: &T, res: SR<u64>) -> Response<'r> {
     let mut response = json_response();
-    match mapping(&conn, &data) {
+    match res {
         Ok(i) => {
             response.sized_body(Cursor::new(serde_json::to_string(&data.new_with_new_id(i)
             ).unwrap()))
@@ -73,17 +219,110 @@ pub fn generic_create<'a, T: ProjectData<'a>>(state: State<Conf>,
     response.finalize()
 }
 
-pub fn generic_update<'a, T: ProjectData<'a>>(state: State<Conf>,
-                                              data: Json<T>,
-                                              mapping: Box<dyn Fn(&Connection, &T) -> SR<()>>,
-) -> Response {
-    let conn = state.get_new_db_connection();
+/// How a PUT or PATCH by id changes the stored object
+pub enum Change {
+    /// PUT, the whole object
+    Replace(Value),
+    /// PATCH with application/merge-patch+json, RFC 7396
+    MergePatch(Value),
+    /// PATCH with application/json-patch+json, RFC 6902
+    JsonPatch(Value),
+}
+
+impl Change {
+    /// The change a PATCH body makes, None when its content type is neither kind of patch
+    pub fn patch(content_type: Option<&ContentType>, body: Value) -> Option<Self> {
+        match content_type.filter(|c| c.top() == "application").map(|c| c.sub().as_str()) {
+            Some("merge-patch+json") => Some(Change::MergePatch(body)),
+            Some("json-patch+json") => Some(Change::JsonPatch(body)),
+            _ => None,
+        }
+    }
+
+    /// The stored object with the change applied, Err when the patch fails or the result is not a valid object
+    fn apply<T: Serialize + DeserializeOwned>(&self, stored: &T) -> Result<T, String> {
+        let changed = match self {
+            Change::Replace(v) => v.clone(),
+            Change::MergePatch(patch) => {
+                let mut v = serde_json::to_value(stored).unwrap();
+                json_patch::merge(&mut v, patch);
+                v
+            }
+            Change::JsonPatch(patch) => {
+                let patch = serde_json::from_value::<json_patch::Patch>(patch.clone()).map_err(|e| e.to_string())?;
+                let mut v = serde_json::to_value(stored).unwrap();
+                json_patch::patch(&mut v, &patch).map_err(|e| e.to_string())?;
+                v
+            }
+        };
+        serde_json::from_value(changed).map_err(|e| e.to_string())
+    }
+}
+
+pub fn unsupported_patch_response() -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage {
+            error: "PATCH takes application/merge-patch+json or application/json-patch+json"
+        }).unwrap()))
+        .status(Status::UnsupportedMediaType)
+        .finalize()
+}
+
+/// Changes the object with the id as one unit of work when If-Match allows it: the changed object must have the id or
+/// none, check validates it against the stored one and mapping writes it.
+/// Responds with the object as stored and its new ETag
+pub fn generic_change<T: for<'de> ProjectData<'de>>(state: State<Conf>,
+                                                    if_match: IfMatch,
+                                                    id: u64,
+                                                    change: Change,
+                                                    get: GetByIdMapping<T>,
+                                                    check: ChangeCheckMapping<T>,
+                                                    mapping: UpdateMapping<T>,
+) -> Response<'static> {
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
+    let mut rejected = None;
+    let mut updated = None;
+    let res = storage.transaction(&mut |s| {
+        rejected = if_match.check(s, state.require_if_match, T::RESOURCE, id)?;
+        if rejected.is_some() {
+            return Ok(());
+        }
+        let stored = get(s, id)?;
+        let mut changed = match change.apply(&stored) {
+            Ok(c) if c.get_id().unwrap_or(id) == id => c.new_with_new_id(id),
+            Ok(_) => {
+                rejected = Some(unprocessable_response("Id does not match the path", "id"));
+                return Ok(());
+            }
+            Err(e) => {
+                rejected = Some(unprocessable_response("Invalid request body", e));
+                return Ok(());
+            }
+        };
+        rejected = check(s, &stored, &mut changed)?;
+        if rejected.is_none() {
+            mapping(s, &changed)?;
+            updated = Some((get(s, id)?, s.revision(T::RESOURCE, id)?));
+        }
+        Ok(())
+    });
+    if let Some(response) = rejected {
+        return response;
+    }
     let mut response = json_response();
-    match mapping(&conn, &data) {
-        Ok(()) => {
-            response.sized_body(Cursor::new(serde_json::to_string(&data.0).unwrap()))
+    match res.map(|()| updated) {
+        Ok(Some((ref object, revision))) => {
+            response.sized_body(Cursor::new(serde_json::to_string(object).unwrap()))
+                .raw_header("ETag", etag(revision))
                 .status(Status::Ok);
         }
+        Err(Error::NotFound) => {
+            response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Id not found" }).unwrap()))
+                .status(Status::NotFound);
+        }
         _ => {
             response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Error" }).unwrap()))
                 .status(Status::InternalServerError);
@@ -92,18 +331,46 @@ pub fn generic_update<'a, T: ProjectData<'a>>(state: State<Conf>,
     response.finalize()
 }
 
+/// 422 for a request body that can not be what it should
+pub fn unprocessable_response<T: Serialize>(error: &'static str, details: T) -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&DetailedErrorMessage { error, details }).unwrap()))
+        .status(Status::UnprocessableEntity)
+        .finalize()
+}
+
+/// Deletes the object when If-Match allows it
 pub fn generic_delete<'a, T: ProjectData<'a>>(state: State<Conf>,
-                                              id: u32,
-                                              mapping: Box<dyn Fn(&Connection, u32) -> SR<()>>,
+                                              if_match: IfMatch,
+                                              id: u64,
+                                              mapping: DeleteMapping,
 ) -> Response {
-    let conn = state.get_new_db_connection();
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
+    let mut rejected = None;
+    let res = storage.transaction(&mut |s| {
+        rejected = if_match.check(s, state.require_if_match, T::RESOURCE, id)?;
+        match rejected {
+            None => mapping(s, id),
+            Some(_) => Ok(()),
+        }
+    });
+    match rejected {
+        Some(response) => response,
+        None => delete_response(id, res),
+    }
+}
+
+fn delete_response<'r>(id: u64, res: SR<()>) -> Response<'r> {
     let mut response = json_response();
-    match mapping(&conn, id) {
+    match res {
         Ok(()) => {
             response.sized_body(Cursor::new(serde_json::to_string(&IdObj { id: Some(id) }).unwrap()))
                 .status(Status::Ok);
         }
-        Err(Error::QueryReturnedNoRows) => {
+        Err(Error::NotFound) => {
             response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Id not found" }).unwrap()))
                 .status(Status::NotFound);
         }
@@ -115,6 +382,67 @@ pub fn generic_delete<'a, T: ProjectData<'a>>(state: State<Conf>,
     response.finalize()
 }
 
+/// generic_delete for objects credentials reference, dependents lists them and cascade deletes them.
+/// on_delete is restrict, the default, or cascade, on restrict responds with 409 listing the dependent credentials
+pub fn generic_delete_referenced<'a, T: ProjectData<'a>>(state: State<Conf>,
+                                                         if_match: IfMatch,
+                                                         id: u64,
+                                                         on_delete: Option<String>,
+                                                         dependents: DependentsMapping,
+                                                         cascade: DeleteMapping,
+                                                         mapping: DeleteMapping,
+) -> Response {
+    let on_delete = match on_delete.as_ref().map(|o| OnDelete::from_name(o)) {
+        None => OnDelete::default(),
+        Some(Some(o)) => o,
+        Some(None) => {
+            return json_response()
+                .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "on_delete must be restrict or cascade" }).unwrap()))
+                .status(Status::BadRequest)
+                .finalize();
+        }
+    };
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
+    let mut rejected = None;
+    let mut credential_ids = vec![];
+    let res = storage.transaction(&mut |s| {
+        rejected = if_match.check(s, state.require_if_match, T::RESOURCE, id)?;
+        if rejected.is_some() {
+            return Ok(());
+        }
+        credential_ids = dependents(s, id)?;
+        if credential_ids.is_empty() {
+            mapping(s, id)
+        } else if on_delete == OnDelete::Cascade {
+            cascade(s, id)?;
+            mapping(s, id)
+        } else {
+            Ok(())
+        }
+    });
+    if let Some(response) = rejected {
+        return response;
+    }
+    if res.is_ok() && !credential_ids.is_empty() && on_delete == OnDelete::Restrict {
+        return referenced_response(credential_ids);
+    }
+    delete_response(id, res)
+}
+
+/// 409 listing the credentials that prevent the object from being deleted or changed
+pub fn referenced_response(credential_ids: Vec<u64>) -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&DetailedErrorMessage {
+            error: "Referenced by credentials",
+            details: Dependents { credential_ids },
+        }).unwrap()))
+        .status(Status::Conflict)
+        .finalize()
+}
+
 pub fn json_response<'a>() -> ResponseBuilder<'a> {
     let mut response = Response::build();
     response.header(ContentType::JSON);
@@ -0,0 +1,77 @@
+use std::io::Cursor;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rocket::{Response, State};
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::Conf;
+use crate::crypto::signer_for;
+use crate::datastructures::{CryptographicKeys, ErrorMessage, Issuer};
+use crate::storage::{Resource, Result as SR, Storage};
+
+use super::internal::*;
+
+/// Response for issuer operations when no issuer_master_key is configured
+pub fn no_master_key_response() -> Response<'static> {
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Issuer master key not configured" }).unwrap()))
+        .status(Status::ServiceUnavailable)
+        .finalize()
+}
+
+/// Id of the key in cryptographic_keys matching the issuer's private key,
+/// storing the public key and linking it to the issuer if there is none
+pub fn issuer_public_key_id(storage: &dyn Storage, issuer: &Issuer, private_key: &[u8]) -> SR<Option<u64>> {
+    let algorithm = issuer.algorithm.unwrap_or_default();
+    let public_key = match signer_for(algorithm).public_key(private_key) {
+        Some(k) => BASE64.encode(k),
+        None => return Ok(None),
+    };
+    if let Some(id) = issuer.public_key_id {
+        if let Ok(k) = storage.get_cryptographic_key(id) {
+            if k.public_key.as_ref() == Some(&public_key) && k.algorithm == Some(algorithm) {
+                return Ok(Some(id));
+            }
+        }
+    }
+    let id = storage.create_cryptographic_key(&CryptographicKeys {
+        public_key: Some(public_key),
+        algorithm: Some(algorithm),
+        revoked: None,
+        expires_at: None,
+        id: None,
+    })?;
+    storage.update_issuer(&Issuer { public_key_id: Some(id), ..issuer.clone() })?;
+    Ok(Some(id))
+}
+
+#[get("/?<limit>&<offset>&<sort>&<fields>")]
+pub fn get_issuers(state: State<Conf>, limit: Option<u32>, offset: Option<u32>, sort: Option<String>, fields: Option<String>) -> Response<'static> {
+    generic_get(state, limit, offset, sort, fields, Box::new(|s, page| s.get_issuers(page)))
+}
+
+#[get("/<id>")]
+pub fn get_issuer_by_id(state: State<Conf>, id: u64) -> Response {
+    generic_get_by_id(state, id, Some(Resource::Issuers), Box::new(|s, id| s.get_issuer(id).map(|i| Issuer { private_key: None, ..i })))
+}
+
+/// Generates a private key of the requested algorithm, the public key is stored on first issuance
+#[post("/", data = "<issuer>")]
+pub fn create_issuer(state: State<Conf>, mut issuer: Json<Issuer>) -> Response {
+    let master_key = match state.inner().master_key {
+        Some(ref k) => k,
+        None => return no_master_key_response(),
+    };
+    let algorithm = issuer.algorithm.unwrap_or_default();
+    issuer.algorithm = Some(algorithm);
+    issuer.public_key_id = None;
+    issuer.private_key = Some(master_key.seal(&signer_for(algorithm).generate()));
+    generic_create(state, issuer, Box::new(|s, data| s.create_issuer(data)))
+}
+
+#[delete("/<id>")]
+pub fn delete_issuer(state: State<Conf>, if_match: IfMatch, id: u64) -> Response {
+    generic_delete::<Issuer>(state, if_match, id, Box::new(|s, id| s.delete_issuer(id)))
+}
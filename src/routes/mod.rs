@@ -1,4 +1,6 @@
 pub mod credentials;
 pub mod cryptographic_keys;
+pub mod issuers;
 pub mod schemas;
+pub mod verify;
 mod internal;
\ No newline at end of file
@@ -1,49 +1,163 @@
 use rocket_contrib::json::Json;
+use serde_json::Value;
 use rocket::http::{Status, ContentType};
 
-use crate::datastructures::{Schema, ErrorMessage};
+use crate::datastructures::{check_schema, DetailedErrorMessage, Schema, ErrorMessage};
 use crate::Conf;
 use rocket::{State, Response};
-use crate::dao::ConnectionRestMapping;
+use crate::compatibility::{changes, classify, CompatibilityReport};
+use crate::jsonschema;
+use crate::storage::{Resource, Result as SR, Storage};
 use std::io::Cursor;
 
 use super::internal::*;
 
-#[get("/?<limit>&<offset>")]
-pub fn get_schemas(state: State<Conf>, limit: Option<u32>, offset: Option<u32>) -> Response {
-    return generic_get(state, limit, offset, Box::new(Schema::get_all));
+/// 400 response for schemas with constraints that cannot be checked, None when the schema is fine
+fn invalid_schema_response(schema: &Schema) -> Option<Response<'static>> {
+    let details = check_schema(schema.schema.as_ref()?).err()?;
+    Some(json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&DetailedErrorMessage { error: "Invalid schema constraints", details }).unwrap()))
+        .status(Status::BadRequest)
+        .finalize())
+}
+
+#[get("/?<limit>&<offset>&<sort>&<fields>")]
+pub fn get_schemas(state: State<Conf>, limit: Option<u32>, offset: Option<u32>, sort: Option<String>, fields: Option<String>) -> Response<'static> {
+    generic_get(state, limit, offset, sort, fields, Box::new(|s, page| s.get_schemas(page)))
 }
 
 #[get("/<id>")]
-pub fn get_schema_by_id(state: State<Conf>, id: u32) -> Response<'static> {
-    return generic_get_by_id(state, id, Box::new(Schema::get_by_id));
+pub fn get_schema_by_id(state: State<Conf>, id: u64) -> Response<'static> {
+    generic_get_by_id(state, id, Some(Resource::Schemas), Box::new(|s, id| s.get_schema(id)))
+}
+
+/// Every version of the schema, oldest first
+#[get("/<id>/versions")]
+pub fn get_schema_versions(state: State<Conf>, id: u64) -> Response<'static> {
+    generic_get_by_id(state, id, None, Box::new(|s, id| s.get_schema_versions(id).map(Some)))
+}
+
+#[get("/<id>/versions/<version>")]
+pub fn get_schema_version(state: State<Conf>, id: u64, version: u32) -> Response<'static> {
+    generic_get_by_id(state, id, None, Box::new(move |s, id| s.get_schema_version(id, version)))
+}
+
+/// How the proposed schema differs from the latest version and which stored credentials would not conform to it
+#[post("/<id>/compatibility", data = "<proposed>")]
+pub fn check_schema_compatibility(state: State<Conf>, id: u64, mut proposed: Json<Schema>) -> Response<'static> {
+    if proposed.schema.is_none() {
+        return json_response()
+            .sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid request body" }).unwrap()))
+            .status(Status::UnprocessableEntity)
+            .finalize();
+    }
+    if let Some(response) = invalid_schema_response(&proposed) {
+        return response;
+    }
+    proposed.additional_properties = Some(proposed.additional_properties.unwrap_or(true));
+    generic_get_by_id(state, id, None, Box::new(move |s, id| {
+        let current = s.get_schema(id)?;
+        let credentials = s.credentials_by_schema_id(id)?;
+        let changes = changes(&current, &proposed);
+        Ok(CompatibilityReport {
+            compatibility: classify(&changes),
+            changes,
+            credentials_checked: credentials.len(),
+            breaking_credential_ids: credentials.iter()
+                .filter(|c| !proposed.validate(c).is_empty())
+                .filter_map(|c| c.id)
+                .collect(),
+        })
+    }))
+}
+
+/// The schema rendered as JSON Schema
+#[get("/<id>/jsonschema")]
+pub fn get_schema_json_schema(state: State<Conf>, id: u64) -> Response<'static> {
+    generic_get_by_id(state, id, None, Box::new(|s, id| s.get_schema(id).map(|s| jsonschema::export(&s))))
+}
+
+/// Creates a schema from a JSON Schema document, as POST /schemas creates the imported one
+#[post("/import/jsonschema", data = "<document>")]
+pub fn import_json_schema(state: State<Conf>, document: Json<Value>) -> Response {
+    match jsonschema::import(&document) {
+        Ok(schema) => create_schema(state, Json(schema)),
+        Err(details) => json_response()
+            .sized_body(Cursor::new(serde_json::to_string(&DetailedErrorMessage { error: "Unsupported JSON Schema", details }).unwrap()))
+            .status(Status::BadRequest)
+            .finalize(),
+    }
 }
 
 #[post("/", data = "<schema>")]
-pub fn create_schema(state: State<Conf>, schema: Json<Schema>) -> Response {
+pub fn create_schema(state: State<Conf>, mut schema: Json<Schema>) -> Response {
     if schema.schema.is_none() {
         let mut response = Response::build();
         response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid schema field" }).unwrap()))
             .status(Status::InternalServerError);
         response.header(ContentType::JSON).finalize()
+    } else if let Some(response) = invalid_schema_response(&schema) {
+        response
     } else {
-        generic_create(state, schema, Box::new(Schema::create))
+        schema.additional_properties = Some(schema.additional_properties.unwrap_or(true));
+        schema.version = Some(1);
+        generic_create(state, schema, Box::new(|s, data| s.create_schema(data)))
     }
 }
 
+/// The schema must be given and its constraints checkable, additional_properties defaults as on creation
+fn check_changed_schema(_: &dyn Storage, _: &Schema, schema: &mut Schema) -> SR<Option<Response<'static>>> {
+    if schema.schema.is_none() {
+        return Ok(Some(unprocessable_response("Invalid request body", "schema is required")));
+    }
+    if let Some(response) = invalid_schema_response(schema) {
+        return Ok(Some(response));
+    }
+    schema.additional_properties = Some(schema.additional_properties.unwrap_or(true));
+    Ok(None)
+}
+
+/// Publishes the next version of the schema, responding with the schema as stored and its new ETag
+fn change_schema(state: State<Conf>, if_match: IfMatch, id: u64, change: Change) -> Response<'static> {
+    generic_change(state, if_match, id, change,
+                   Box::new(|s, id| s.get_schema(id)),
+                   Box::new(check_changed_schema),
+                   Box::new(|s, data| s.update_schema(data)))
+}
+
+/// Same as PUT /schemas/<id> with the id in the body
 #[put("/", data = "<schema>")]
-pub fn update_schema(state: State<Conf>, schema: Json<Schema>) -> Response {
-    if schema.schema.is_some() && schema.id.is_some() {
-        generic_update(state, schema, Box::new(Schema::update))
-    } else {
-        let mut response = Response::build();
-        response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid request body" }).unwrap()))
-            .status(Status::UnprocessableEntity);
-        response.header(ContentType::JSON).finalize()
+pub fn update_schema(state: State<Conf>, if_match: IfMatch, schema: Json<Value>) -> Response<'static> {
+    match schema.get("id").and_then(Value::as_u64) {
+        Some(id) => change_schema(state, if_match, id, Change::Replace(schema.into_inner())),
+        None => {
+            let mut response = Response::build();
+            response.sized_body(Cursor::new(serde_json::to_string(&ErrorMessage { error: "Invalid request body" }).unwrap()))
+                .status(Status::UnprocessableEntity);
+            response.header(ContentType::JSON).finalize()
+        }
+    }
+}
+
+#[put("/<id>", data = "<schema>")]
+pub fn replace_schema(state: State<Conf>, if_match: IfMatch, id: u64, schema: Json<Value>) -> Response<'static> {
+    change_schema(state, if_match, id, Change::Replace(schema.into_inner()))
+}
+
+/// The name is kept when the patched schema has none, the version is always the server's
+#[patch("/<id>", data = "<patch>")]
+pub fn patch_schema(state: State<Conf>, if_match: IfMatch, content_type: Option<&ContentType>, id: u64, patch: Json<Value>) -> Response<'static> {
+    match Change::patch(content_type, patch.into_inner()) {
+        Some(change) => change_schema(state, if_match, id, change),
+        None => unsupported_patch_response(),
     }
 }
 
-#[delete("/<id>")]
-pub fn delete_schema(state: State<Conf>, id: u32) -> Response {
-    return generic_delete::<Schema>(state, id, Box::new(Schema::delete_by_id));
+/// on_delete is restrict or cascade, deciding what happens to credentials of the schema
+#[delete("/<id>?<on_delete>")]
+pub fn delete_schema(state: State<Conf>, if_match: IfMatch, id: u64, on_delete: Option<String>) -> Response {
+    generic_delete_referenced::<Schema>(state, if_match, id, on_delete,
+                                        Box::new(|s, id| s.credential_ids_by_schema_id(id)),
+                                        Box::new(|s, id| s.delete_credentials_by_schema_id(id)),
+                                        Box::new(|s, id| s.delete_schema(id)))
 }
@@ -0,0 +1,25 @@
+use std::io::Cursor;
+
+use rocket::{Response, State};
+use rocket::http::Status;
+use rocket_contrib::json::Json;
+
+use crate::Conf;
+use crate::datastructures::VerifyRequest;
+
+use super::credentials::verify_credential;
+use super::internal::*;
+
+/// Verifies a credential that is not necessarily stored here, reporting on every check instead of failing on the first
+#[post("/", data = "<req>")]
+pub fn verify(state: State<Conf>, req: Json<VerifyRequest>) -> Response {
+    let storage = match state.get_storage() {
+        Ok(s) => s,
+        Err(_) => return unavailable_response(),
+    };
+    let report = verify_credential(&*storage, &req.credential, req.public_key.as_ref());
+    json_response()
+        .sized_body(Cursor::new(serde_json::to_string(&report).unwrap()))
+        .status(Status::Ok)
+        .finalize()
+}
@@ -0,0 +1,478 @@
+use std::cell::RefCell;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::datastructures::{Credential, CredentialFilter, CryptographicKeys, Issuer, KeyFilter, Page, Schema, Sort};
+
+use super::{Backend, Error, Resource, Result, Storage};
+
+pub const CREDENTIALS: &str = "credentials";
+pub const SCHEMAS: &str = "schemas";
+/// Keyed by schema id then version
+pub const SCHEMA_VERSIONS: &str = "schema_versions";
+pub const CRYPTOGRAPHIC_KEYS: &str = "cryptographic_keys";
+pub const ISSUERS: &str = "issuers";
+/// The last id handed out in each table, keyed by table name, ids are never reused
+pub const SEQUENCES: &str = "sequences";
+/// The revision of each object, keyed by table name then id
+pub const REVISIONS: &str = "revisions";
+
+pub const TABLES: [&str; 7] = [CREDENTIALS, SCHEMAS, SCHEMA_VERSIONS, CRYPTOGRAPHIC_KEYS, ISSUERS, SEQUENCES, REVISIONS];
+
+/// Reads of a transaction, keys sort bytewise
+pub trait Read {
+    fn get(&self, table: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>>;
+    /// Entries whose key starts with prefix, in key order
+    fn scan(&self, table: &'static str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>>;
+}
+
+pub trait Write: Read {
+    fn put(&mut self, table: &'static str, key: &[u8], value: &[u8]) -> Result<()>;
+    /// false when there was nothing to delete
+    fn delete(&mut self, table: &'static str, key: &[u8]) -> Result<bool>;
+    fn as_read(&self) -> &dyn Read;
+}
+
+/// A store of ordered tables of bytes, each read and write runs in its own transaction,
+/// writes are discarded when f fails
+pub trait KeyValue {
+    fn read<R>(&self, f: impl FnOnce(&dyn Read) -> Result<R>) -> Result<R>;
+    fn write<R>(&self, f: impl FnOnce(&mut dyn Write) -> Result<R>) -> Result<R>;
+}
+
+impl<K: KeyValue + Send + Sync> Backend for K {
+    fn storage(&self) -> Result<Box<dyn Storage + '_>> {
+        Ok(Box::new(Kv(self)))
+    }
+}
+
+/// Ids are big endian so that keys sort by id
+fn id_key(id: u64) -> [u8; 8] {
+    id.to_be_bytes()
+}
+
+fn revision_key(table: &'static str, id: u64) -> Vec<u8> {
+    [table.as_bytes(), &id.to_be_bytes()[..]].concat()
+}
+
+fn version_key(id: u64, version: u32) -> Vec<u8> {
+    [&id.to_be_bytes()[..], &version.to_be_bytes()[..]].concat()
+}
+
+fn decode<T: DeserializeOwned>(value: &[u8]) -> Result<T> {
+    serde_json::from_slice(value).map_err(|e| Error::Backend(format!("corrupt record: {}", e)))
+}
+
+fn get<T: DeserializeOwned, R: Read + ?Sized>(txn: &R, table: &'static str, key: &[u8]) -> Result<Option<T>> {
+    txn.get(table, key)?.map(|v| decode(&v)).transpose()
+}
+
+/// Like get, with Err(NotFound) when there is nothing
+fn find<T: DeserializeOwned, R: Read + ?Sized>(txn: &R, table: &'static str, id: u64) -> Result<T> {
+    get(txn, table, &id_key(id))?.ok_or(Error::NotFound)
+}
+
+fn exists<R: Read + ?Sized>(txn: &R, table: &'static str, id: u64) -> Result<bool> {
+    Ok(txn.get(table, &id_key(id))?.is_some())
+}
+
+fn all<T: DeserializeOwned, R: Read + ?Sized>(txn: &R, table: &'static str) -> Result<Vec<T>> {
+    txn.scan(table, &[])?.iter().map(|(_, v)| decode(v)).collect()
+}
+
+/// The page of objects in id order
+fn paged<T>(mut all: Vec<T>, page: Page) -> Vec<T> {
+    if page.sort == Sort::Descending {
+        all.reverse();
+    }
+    all.into_iter()
+        .skip(page.offset.unwrap_or(0) as usize)
+        .take(page.limit.map_or(usize::MAX, |l| l as usize))
+        .collect()
+}
+
+fn put<T: Serialize, W: Write + ?Sized>(txn: &mut W, table: &'static str, key: &[u8], value: &T) -> Result<()> {
+    txn.put(table, key, &serde_json::to_vec(value).unwrap())
+}
+
+/// Stores the object under its id, incrementing its revision
+fn put_object<T: Serialize, W: Write + ?Sized>(txn: &mut W, table: &'static str, id: u64, value: &T) -> Result<()> {
+    let revision = get::<u64, _>(txn, REVISIONS, &revision_key(table, id))?.unwrap_or(0) + 1;
+    put(txn, REVISIONS, &revision_key(table, id), &revision)?;
+    put(txn, table, &id_key(id), value)
+}
+
+/// Deletes the object with its revision
+fn delete<W: Write + ?Sized>(txn: &mut W, table: &'static str, id: u64) -> Result<()> {
+    txn.delete(REVISIONS, &revision_key(table, id))?;
+    if txn.delete(table, &id_key(id))? {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+fn next_id<W: Write + ?Sized>(txn: &mut W, table: &'static str) -> Result<u64> {
+    let id = get::<u64, _>(txn, SEQUENCES, table.as_bytes())?.unwrap_or(0) + 1;
+    put(txn, SEQUENCES, table.as_bytes(), &id)?;
+    Ok(id)
+}
+
+fn check_reference<R: Read + ?Sized>(txn: &R, table: &'static str, id: Option<u64>) -> Result<()> {
+    match id {
+        Some(id) if !exists(txn, table, id)? => Err(Error::Constraint(format!("no {} with id {}", table, id))),
+        _ => Ok(()),
+    }
+}
+
+fn referencing<R: Read + ?Sized>(txn: &R, refers: impl Fn(&Credential) -> bool) -> Result<Vec<Credential>> {
+    Ok(all::<Credential, _>(txn, CREDENTIALS)?.into_iter().filter(refers).collect())
+}
+
+fn ids(credentials: Vec<Credential>) -> Vec<u64> {
+    credentials.into_iter().filter_map(|c| c.id).collect()
+}
+
+/// Issuer leaves private_key out when serialized
+#[derive(Deserialize, Serialize)]
+struct IssuerRecord {
+    #[serde(flatten)]
+    issuer: Issuer,
+    private_key: Option<Vec<u8>>,
+}
+
+impl From<IssuerRecord> for Issuer {
+    fn from(r: IssuerRecord) -> Self {
+        Issuer { private_key: r.private_key, ..r.issuer }
+    }
+}
+
+impl From<&Issuer> for IssuerRecord {
+    fn from(i: &Issuer) -> Self {
+        IssuerRecord { issuer: i.clone(), private_key: i.private_key.clone() }
+    }
+}
+
+/// Storage over a KeyValue, objects are stored as JSON under their id. Lookups by reference scan the credentials,
+/// references are checked as SQLite's foreign keys would
+pub struct Kv<'a, K>(&'a K);
+
+/// The write transaction of a unit of work, every read and write of its Storage runs in it
+struct UnitOfWork<'t>(RefCell<&'t mut dyn Write>);
+
+impl KeyValue for UnitOfWork<'_> {
+    fn read<R>(&self, f: impl FnOnce(&dyn Read) -> Result<R>) -> Result<R> {
+        f(self.0.borrow().as_read())
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut dyn Write) -> Result<R>) -> Result<R> {
+        f(&mut **self.0.borrow_mut())
+    }
+}
+
+impl<K: KeyValue> Storage for Kv<'_, K> {
+    fn transaction(&self, f: &mut dyn FnMut(&dyn Storage) -> Result<()>) -> Result<()> {
+        self.0.write(|txn| f(&Kv(&UnitOfWork(RefCell::new(txn)))))
+    }
+
+    /// Objects stored before revisions were kept are at revision 0
+    fn revision(&self, resource: Resource, id: u64) -> Result<u64> {
+        let table = resource.table();
+        self.0.read(|txn| {
+            if !exists(txn, table, id)? {
+                return Err(Error::NotFound);
+            }
+            Ok(get(txn, REVISIONS, &revision_key(table, id))?.unwrap_or(0))
+        })
+    }
+
+    fn get_credential(&self, id: u64) -> Result<Credential> {
+        self.0.read(|txn| find(txn, CREDENTIALS, id))
+    }
+
+    fn get_credentials(&self, filter: CredentialFilter, page: Page) -> Result<Vec<Credential>> {
+        self.0.read(|txn| Ok(paged(referencing(txn, |c| filter.matches(c))?, page)))
+    }
+
+    fn create_credential(&self, data: &Credential) -> Result<u64> {
+        self.0.write(|txn| {
+            let schema_id = match data.schema_id {
+                Some(id) => id,
+                None => return Err(Error::Constraint("credentials need a schema_id".to_owned())),
+            };
+            check_reference(txn, SCHEMAS, data.schema_id)?;
+            check_reference(txn, CRYPTOGRAPHIC_KEYS, data.public_key_id)?;
+            // Pinned to the latest version when there is none, the version must exist
+            let schema_version = match data.schema_version {
+                Some(version) => version,
+                None => find::<Schema, _>(txn, SCHEMAS, schema_id)?.version.unwrap_or(1),
+            };
+            if txn.get(SCHEMA_VERSIONS, &version_key(schema_id, schema_version))?.is_none() {
+                return Err(Error::Constraint(format!("no version {} of schema {}", schema_version, schema_id)));
+            }
+            let id = next_id(txn, CREDENTIALS)?;
+            let credential = Credential { id: Some(id), schema_version: Some(schema_version), ..data.clone() };
+            put_object(txn, CREDENTIALS, id, &credential)?;
+            Ok(id)
+        })
+    }
+
+    fn update_credential(&self, data: &Credential) -> Result<()> {
+        let id = data.id.ok_or(Error::NotFound)?;
+        self.0.write(|txn| {
+            let stored: Credential = find(txn, CREDENTIALS, id)?;
+            let credential = Credential { data: data.data.clone(), finger_print: data.finger_print.clone(), ..stored };
+            put_object(txn, CREDENTIALS, id, &credential)
+        })
+    }
+
+    fn delete_credential(&self, id: u64) -> Result<()> {
+        self.0.write(|txn| delete(txn, CREDENTIALS, id))
+    }
+
+    fn credentials_by_schema_id(&self, schema_id: u64) -> Result<Vec<Credential>> {
+        self.0.read(|txn| referencing(txn, |c| c.schema_id == Some(schema_id)))
+    }
+
+    fn credential_ids_by_schema_id(&self, schema_id: u64) -> Result<Vec<u64>> {
+        self.credentials_by_schema_id(schema_id).map(ids)
+    }
+
+    fn credential_ids_by_public_key_id(&self, public_key_id: u64) -> Result<Vec<u64>> {
+        self.0.read(|txn| referencing(txn, |c| c.public_key_id == Some(public_key_id)).map(ids))
+    }
+
+    fn delete_credentials_by_schema_id(&self, schema_id: u64) -> Result<()> {
+        self.0.write(|txn| {
+            for id in ids(referencing(txn, |c| c.schema_id == Some(schema_id))?) {
+                delete(txn, CREDENTIALS, id)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_credentials_by_public_key_id(&self, public_key_id: u64) -> Result<()> {
+        self.0.write(|txn| {
+            for id in ids(referencing(txn, |c| c.public_key_id == Some(public_key_id))?) {
+                delete(txn, CREDENTIALS, id)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn get_schema(&self, id: u64) -> Result<Schema> {
+        self.0.read(|txn| find(txn, SCHEMAS, id))
+    }
+
+    fn get_schemas(&self, page: Page) -> Result<Vec<Schema>> {
+        self.0.read(|txn| Ok(paged(all(txn, SCHEMAS)?, page)))
+    }
+
+    fn create_schema(&self, data: &Schema) -> Result<u64> {
+        self.0.write(|txn| {
+            let id = next_id(txn, SCHEMAS)?;
+            let schema = Schema {
+                id: Some(id),
+                version: Some(1),
+                additional_properties: Some(data.additional_properties.unwrap_or(true)),
+                ..data.clone()
+            };
+            put_object(txn, SCHEMAS, id, &schema)?;
+            put(txn, SCHEMA_VERSIONS, &version_key(id, 1), &schema)?;
+            Ok(id)
+        })
+    }
+
+    fn update_schema(&self, data: &Schema) -> Result<()> {
+        let id = data.id.ok_or(Error::NotFound)?;
+        self.0.write(|txn| {
+            let stored: Schema = find(txn, SCHEMAS, id)?;
+            let version = stored.version.unwrap_or(1) + 1;
+            if txn.get(SCHEMA_VERSIONS, &version_key(id, version))?.is_some() {
+                return Err(Error::Constraint(format!("schema {} version {} is already published", id, version)));
+            }
+            let schema = Schema {
+                name: data.name.clone().or(stored.name),
+                schema: data.schema.clone(),
+                additional_properties: Some(data.additional_properties.unwrap_or(true)),
+                version: Some(version),
+                id: Some(id),
+            };
+            put(txn, SCHEMA_VERSIONS, &version_key(id, version), &schema)?;
+            put_object(txn, SCHEMAS, id, &schema)
+        })
+    }
+
+    fn delete_schema(&self, id: u64) -> Result<()> {
+        self.0.write(|txn| {
+            if !referencing(txn, |c| c.schema_id == Some(id))?.is_empty() {
+                return Err(Error::Constraint(format!("credentials reference schema {}", id)));
+            }
+            for (key, _) in txn.scan(SCHEMA_VERSIONS, &id_key(id))? {
+                txn.delete(SCHEMA_VERSIONS, &key)?;
+            }
+            delete(txn, SCHEMAS, id)
+        })
+    }
+
+    /// Versions carry the schema's current name
+    fn get_schema_version(&self, id: u64, version: u32) -> Result<Schema> {
+        self.0.read(|txn| {
+            let stored: Schema = find(txn, SCHEMAS, id)?;
+            let schema: Schema = get(txn, SCHEMA_VERSIONS, &version_key(id, version))?.ok_or(Error::NotFound)?;
+            Ok(Schema { name: stored.name, ..schema })
+        })
+    }
+
+    fn get_schema_versions(&self, id: u64) -> Result<Vec<Schema>> {
+        self.0.read(|txn| {
+            let stored: Schema = find(txn, SCHEMAS, id)?;
+            txn.scan(SCHEMA_VERSIONS, &id_key(id))?.iter()
+                .map(|(_, v)| decode(v).map(|s: Schema| Schema { name: stored.name.clone(), ..s }))
+                .collect()
+        })
+    }
+
+    fn get_cryptographic_key(&self, id: u64) -> Result<CryptographicKeys> {
+        self.0.read(|txn| find(txn, CRYPTOGRAPHIC_KEYS, id))
+    }
+
+    fn get_cryptographic_keys(&self, filter: KeyFilter, page: Page) -> Result<Vec<CryptographicKeys>> {
+        self.0.read(|txn| {
+            let keys = all::<CryptographicKeys, _>(txn, CRYPTOGRAPHIC_KEYS)?;
+            Ok(paged(keys.into_iter().filter(|k| filter.matches(k)).collect(), page))
+        })
+    }
+
+    fn create_cryptographic_key(&self, data: &CryptographicKeys) -> Result<u64> {
+        self.0.write(|txn| {
+            let id = next_id(txn, CRYPTOGRAPHIC_KEYS)?;
+            put_object(txn, CRYPTOGRAPHIC_KEYS, id, &stored_key(id, data))?;
+            Ok(id)
+        })
+    }
+
+    fn update_cryptographic_key(&self, data: &CryptographicKeys) -> Result<()> {
+        let id = data.id.ok_or(Error::NotFound)?;
+        self.0.write(|txn| {
+            find::<CryptographicKeys, _>(txn, CRYPTOGRAPHIC_KEYS, id)?;
+            put_object(txn, CRYPTOGRAPHIC_KEYS, id, &stored_key(id, data))
+        })
+    }
+
+    fn delete_cryptographic_key(&self, id: u64) -> Result<()> {
+        self.0.write(|txn| {
+            if !referencing(txn, |c| c.public_key_id == Some(id))?.is_empty() {
+                return Err(Error::Constraint(format!("credentials reference cryptographic key {}", id)));
+            }
+            for record in all::<IssuerRecord, _>(txn, ISSUERS)? {
+                if record.issuer.public_key_id == Some(id) {
+                    let record = IssuerRecord { issuer: Issuer { public_key_id: None, ..record.issuer }, ..record };
+                    put_object(txn, ISSUERS, record.issuer.id.unwrap(), &record)?;
+                }
+            }
+            delete(txn, CRYPTOGRAPHIC_KEYS, id)
+        })
+    }
+
+    fn get_issuer(&self, id: u64) -> Result<Issuer> {
+        self.0.read(|txn| find::<IssuerRecord, _>(txn, ISSUERS, id).map(Issuer::from))
+    }
+
+    fn get_issuers(&self, page: Page) -> Result<Vec<Issuer>> {
+        self.0.read(|txn| Ok(paged(all::<IssuerRecord, _>(txn, ISSUERS)?, page).into_iter().map(|r| r.issuer).collect()))
+    }
+
+    fn create_issuer(&self, data: &Issuer) -> Result<u64> {
+        self.0.write(|txn| {
+            check_reference(txn, CRYPTOGRAPHIC_KEYS, data.public_key_id)?;
+            let id = next_id(txn, ISSUERS)?;
+            let issuer = Issuer { id: Some(id), algorithm: Some(data.algorithm.unwrap_or_default()), ..data.clone() };
+            put_object(txn, ISSUERS, id, &IssuerRecord::from(&issuer))?;
+            Ok(id)
+        })
+    }
+
+    fn update_issuer(&self, data: &Issuer) -> Result<()> {
+        let id = data.id.ok_or(Error::NotFound)?;
+        self.0.write(|txn| {
+            let stored: IssuerRecord = find(txn, ISSUERS, id)?;
+            check_reference(txn, CRYPTOGRAPHIC_KEYS, data.public_key_id)?;
+            let record = IssuerRecord { issuer: Issuer { public_key_id: data.public_key_id, ..stored.issuer }, ..stored };
+            put_object(txn, ISSUERS, id, &record)
+        })
+    }
+
+    fn delete_issuer(&self, id: u64) -> Result<()> {
+        self.0.write(|txn| delete(txn, ISSUERS, id))
+    }
+}
+
+/// The key as SQLite would store it, with its defaults filled in
+fn stored_key(id: u64, data: &CryptographicKeys) -> CryptographicKeys {
+    CryptographicKeys {
+        id: Some(id),
+        algorithm: Some(data.algorithm.unwrap_or_default()),
+        revoked: Some(data.is_revoked()),
+        ..data.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::storage::memory::MemoryBackend;
+
+    use super::*;
+
+    #[test]
+    fn ids_are_never_reused() {
+        let backend = MemoryBackend::default();
+        let storage = backend.storage().unwrap();
+        let schema = serde_json::from_str::<Schema>("{\"schema\": {\"a\": \"Bool\"}}").unwrap();
+        let first = storage.create_schema(&schema).unwrap();
+        storage.delete_schema(first).unwrap();
+        let second = storage.create_schema(&schema).unwrap();
+        assert!(second > first);
+        assert!(matches!(storage.get_schema(first), Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn failed_writes_leave_nothing() {
+        let backend = MemoryBackend::default();
+        backend.write(|txn| put_object(txn, ISSUERS, 1, &1)).unwrap();
+        let res = backend.write(|txn| {
+            put_object(txn, ISSUERS, 1, &2)?;
+            next_id(txn, ISSUERS)?;
+            delete(txn, ISSUERS, 2)
+        });
+        assert!(matches!(res, Err(Error::NotFound)));
+        backend.read(|txn| {
+            assert_eq!(get::<u64, _>(txn, ISSUERS, &id_key(1))?, Some(1));
+            assert!(txn.scan(SEQUENCES, &[])?.is_empty());
+            Ok(())
+        }).unwrap();
+    }
+
+    #[test]
+    fn transactions_are_all_or_nothing() {
+        let backend = MemoryBackend::default();
+        let storage = backend.storage().unwrap();
+        let schema = serde_json::from_str::<Schema>("{\"schema\": {\"a\": \"Bool\"}}").unwrap();
+        let res = storage.transaction(&mut |s| {
+            let id = s.create_schema(&schema)?;
+            s.transaction(&mut |s| s.update_schema(&Schema { id: Some(id), ..schema.clone() }))?;
+            s.delete_issuer(1)
+        });
+        assert!(matches!(res, Err(Error::NotFound)));
+        assert!(storage.get_schemas(Page::default()).unwrap().is_empty());
+        storage.transaction(&mut |s| s.create_schema(&schema).map(|_| ())).unwrap();
+        assert_eq!(storage.get_schemas(Page::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn keys_sort_by_id() {
+        assert!(id_key(255) < id_key(256));
+        assert!(version_key(1, u32::MAX) < version_key(2, 0));
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::fs;
+
+use ::lmdb::{Cursor, Database, DatabaseFlags, Environment, EnvironmentFlags, RwTransaction, Transaction, WriteFlags};
+
+use crate::database::DatabaseConfig;
+
+use super::kv::{KeyValue, Read, Write, TABLES};
+use super::{Error, Result};
+
+/// Largest the LMDB map may grow to, the file only takes the space it uses
+const MAP_SIZE: usize = 1 << 30;
+
+impl From<::lmdb::Error> for Error {
+    fn from(e: ::lmdb::Error) -> Self {
+        Error::Backend(e.to_string())
+    }
+}
+
+/// An LMDB environment in the database_path directory, with a database per table
+pub struct LmdbBackend {
+    env: Environment,
+    databases: HashMap<&'static str, Database>,
+}
+
+impl LmdbBackend {
+    /// The directory and its databases are only created when create is set
+    pub fn open(database: &DatabaseConfig, create: bool) -> std::result::Result<Self, String> {
+        let path = database.path.display();
+        if create {
+            fs::create_dir_all(&database.path).map_err(|e| format!("database {} can not be created: {}", path, e))?;
+        } else if !database.path.is_dir() {
+            return Err(format!("database {} does not exist, create it with --migrate-only", path));
+        }
+        let mut flags = EnvironmentFlags::NO_TLS;
+        if database.read_only {
+            flags |= EnvironmentFlags::READ_ONLY;
+        }
+        let env = Environment::new()
+            .set_flags(flags)
+            .set_max_dbs(TABLES.len() as u32)
+            .set_map_size(MAP_SIZE)
+            .open(&database.path)
+            .map_err(|e| format!("database {} can not be opened: {}", path, e))?;
+        let mut databases = HashMap::new();
+        for table in TABLES.iter() {
+            let db = if create && !database.read_only {
+                env.create_db(Some(table), DatabaseFlags::empty())
+            } else {
+                env.open_db(Some(table))
+            }.map_err(|e| format!("database {} has no table {}, create it with --migrate-only: {}", path, table, e))?;
+            databases.insert(*table, db);
+        }
+        Ok(LmdbBackend { env, databases })
+    }
+}
+
+struct LmdbTransaction<'a, T> {
+    txn: T,
+    databases: &'a HashMap<&'static str, Database>,
+}
+
+impl<T: Transaction> Read for LmdbTransaction<'_, T> {
+    fn get(&self, table: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.txn.get(self.databases[table], &key) {
+            Ok(v) => Ok(Some(v.to_vec())),
+            Err(::lmdb::Error::NotFound) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn scan(&self, table: &'static str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let mut cursor = self.txn.open_ro_cursor(self.databases[table])?;
+        // Keys are sorted, those with the prefix follow the first key not less than it.
+        // LMDB refuses empty keys, every key has the empty prefix
+        let entries = if prefix.is_empty() { cursor.iter_start() } else { cursor.iter_from(prefix) };
+        let mut res = vec![];
+        for entry in entries {
+            let (k, v) = entry?;
+            if !k.starts_with(prefix) {
+                break;
+            }
+            res.push((k.to_vec(), v.to_vec()));
+        }
+        Ok(res)
+    }
+}
+
+impl Write for LmdbTransaction<'_, RwTransaction<'_>> {
+    fn put(&mut self, table: &'static str, key: &[u8], value: &[u8]) -> Result<()> {
+        Ok(self.txn.put(self.databases[table], &key, &value, WriteFlags::empty())?)
+    }
+
+    fn delete(&mut self, table: &'static str, key: &[u8]) -> Result<bool> {
+        match self.txn.del(self.databases[table], &key, None) {
+            Ok(()) => Ok(true),
+            Err(::lmdb::Error::NotFound) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+}
+
+impl KeyValue for LmdbBackend {
+    fn read<R>(&self, f: impl FnOnce(&dyn Read) -> Result<R>) -> Result<R> {
+        let txn = LmdbTransaction { txn: self.env.begin_ro_txn()?, databases: &self.databases };
+        f(&txn)
+    }
+
+    /// Writers wait for each other, readers are never blocked
+    fn write<R>(&self, f: impl FnOnce(&mut dyn Write) -> Result<R>) -> Result<R> {
+        let mut txn = LmdbTransaction { txn: self.env.begin_rw_txn()?, databases: &self.databases };
+        let res = f(&mut txn)?;
+        txn.txn.commit()?;
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, process};
+
+    use crate::database::BackendKind;
+    use crate::storage::kv::SCHEMAS;
+
+    use super::*;
+
+    #[test]
+    fn scan_starts_at_the_prefix() {
+        let path = env::temp_dir().join(format!("rust-exercise-scan-{}.lmdb", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        let database = DatabaseConfig { path: path.clone(), backend: BackendKind::Lmdb, ..DatabaseConfig::default() };
+        let backend = LmdbBackend::open(&database, true).unwrap();
+        backend.write(|txn| {
+            for key in ["a1", "b1", "b2", "c1"].iter() {
+                txn.put(SCHEMAS, key.as_bytes(), b"")?;
+            }
+            Ok(())
+        }).unwrap();
+        let keys = |prefix: &str| backend.read(|txn| txn.scan(SCHEMAS, prefix.as_bytes())).unwrap()
+            .into_iter().map(|(k, _)| String::from_utf8(k).unwrap()).collect::<Vec<_>>();
+        assert_eq!(keys("b"), ["b1", "b2"]);
+        assert_eq!(keys(""), ["a1", "b1", "b2", "c1"]);
+        assert_eq!(keys("a2"), Vec::<String>::new());
+        assert_eq!(keys("d"), Vec::<String>::new());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
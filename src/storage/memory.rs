@@ -0,0 +1,83 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+
+use super::kv::{KeyValue, Read, Write};
+use super::Result;
+
+type Tables = HashMap<&'static str, BTreeMap<Vec<u8>, Vec<u8>>>;
+/// A table and key with the value it held before the write
+type Undo = (&'static str, Vec<u8>, Option<Vec<u8>>);
+
+/// Keeps everything in memory, for development and tests, nothing survives a restart
+#[derive(Default)]
+pub struct MemoryBackend(RwLock<Tables>);
+
+impl Read for Tables {
+    fn get(&self, table: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Ok(HashMap::get(self, table).and_then(|t| t.get(key)).cloned())
+    }
+
+    fn scan(&self, table: &'static str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Ok(HashMap::get(self, table).map_or(vec![], |t| t.range(prefix.to_vec()..)
+            .take_while(|(k, _)| k.starts_with(prefix))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()))
+    }
+}
+
+/// Writes straight to the tables, remembering what they replaced to undo them if the transaction fails
+struct MemoryWrite<'a> {
+    tables: &'a mut Tables,
+    undo: Vec<Undo>,
+}
+
+impl Read for MemoryWrite<'_> {
+    fn get(&self, table: &'static str, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        Read::get(&*self.tables, table, key)
+    }
+
+    fn scan(&self, table: &'static str, prefix: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        Read::scan(&*self.tables, table, prefix)
+    }
+}
+
+impl Write for MemoryWrite<'_> {
+    fn put(&mut self, table: &'static str, key: &[u8], value: &[u8]) -> Result<()> {
+        let old = self.tables.entry(table).or_default().insert(key.to_vec(), value.to_vec());
+        self.undo.push((table, key.to_vec(), old));
+        Ok(())
+    }
+
+    fn delete(&mut self, table: &'static str, key: &[u8]) -> Result<bool> {
+        let old = self.tables.entry(table).or_default().remove(key);
+        let deleted = old.is_some();
+        self.undo.push((table, key.to_vec(), old));
+        Ok(deleted)
+    }
+
+    fn as_read(&self) -> &dyn Read {
+        self
+    }
+}
+
+impl KeyValue for MemoryBackend {
+    fn read<R>(&self, f: impl FnOnce(&dyn Read) -> Result<R>) -> Result<R> {
+        f(&*self.0.read().unwrap())
+    }
+
+    fn write<R>(&self, f: impl FnOnce(&mut dyn Write) -> Result<R>) -> Result<R> {
+        let mut tables = self.0.write().unwrap();
+        let mut txn = MemoryWrite { tables: &mut tables, undo: vec![] };
+        let res = f(&mut txn);
+        if res.is_err() {
+            for (table, key, old) in txn.undo.into_iter().rev() {
+                let t = txn.tables.entry(table).or_default();
+                match old {
+                    Some(v) => t.insert(key, v),
+                    None => t.remove(&key),
+                };
+            }
+        }
+        res
+    }
+}
@@ -0,0 +1,134 @@
+use std::fmt;
+
+use rusqlite::ErrorCode;
+
+use crate::database::{BackendKind, DatabaseConfig, Migration};
+use crate::datastructures::{Credential, CredentialFilter, CryptographicKeys, Issuer, KeyFilter, Page, Schema};
+
+pub mod kv;
+pub mod lmdb;
+pub mod memory;
+pub mod sqlite;
+
+#[derive(Debug)]
+pub enum Error {
+    /// No object has the id
+    NotFound,
+    /// The write would leave a reference to a missing object, or remove a referenced one
+    Constraint(String),
+    Backend(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "not found"),
+            Error::Constraint(e) => write!(f, "constraint violated: {}", e),
+            Error::Backend(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match e {
+            rusqlite::Error::QueryReturnedNoRows => Error::NotFound,
+            rusqlite::Error::SqliteFailure(ref f, _) if f.code == ErrorCode::ConstraintViolation => Error::Constraint(e.to_string()),
+            e => Error::Backend(e.to_string()),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// The kinds of object with an id, named after their table
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    Credentials,
+    Schemas,
+    CryptographicKeys,
+    Issuers,
+}
+
+impl Resource {
+    pub fn table(self) -> &'static str {
+        match self {
+            Resource::Credentials => "credentials",
+            Resource::Schemas => "schemas",
+            Resource::CryptographicKeys => "cryptographic_keys",
+            Resource::Issuers => "issuers",
+        }
+    }
+}
+
+/// Everything the routes read and write, whichever backend stores it.
+/// Getters of a single object answer Err(NotFound) when there is none
+pub trait Storage {
+    /// Runs f as one unit of work on the storage it is given: nothing else is written between what f reads and what it
+    /// writes, and its writes are all kept when it returns Ok and all undone otherwise.
+    /// f should return the Err of an operation that fails, its writes are only undone with the whole unit of work
+    fn transaction(&self, f: &mut dyn FnMut(&dyn Storage) -> Result<()>) -> Result<()>;
+
+    /// Changes whenever the object is updated, Err(NotFound) when there is none
+    fn revision(&self, resource: Resource, id: u64) -> Result<u64>;
+
+    fn get_credential(&self, id: u64) -> Result<Credential>;
+    fn get_credentials(&self, filter: CredentialFilter, page: Page) -> Result<Vec<Credential>>;
+    fn create_credential(&self, data: &Credential) -> Result<u64>;
+    /// Only data and finger_print change
+    fn update_credential(&self, data: &Credential) -> Result<()>;
+    fn delete_credential(&self, id: u64) -> Result<()>;
+    /// Every credential of the schema, whatever version it is pinned to
+    fn credentials_by_schema_id(&self, schema_id: u64) -> Result<Vec<Credential>>;
+    fn credential_ids_by_schema_id(&self, schema_id: u64) -> Result<Vec<u64>>;
+    fn credential_ids_by_public_key_id(&self, public_key_id: u64) -> Result<Vec<u64>>;
+    fn delete_credentials_by_schema_id(&self, schema_id: u64) -> Result<()>;
+    fn delete_credentials_by_public_key_id(&self, public_key_id: u64) -> Result<()>;
+
+    fn get_schema(&self, id: u64) -> Result<Schema>;
+    fn get_schemas(&self, page: Page) -> Result<Vec<Schema>>;
+    /// Creates version 1
+    fn create_schema(&self, data: &Schema) -> Result<u64>;
+    /// Publishes the next version, published versions never change, the name is kept when data has none
+    fn update_schema(&self, data: &Schema) -> Result<()>;
+    /// Deletes every version, refused while credentials reference the schema
+    fn delete_schema(&self, id: u64) -> Result<()>;
+    fn get_schema_version(&self, id: u64, version: u32) -> Result<Schema>;
+    /// Oldest first
+    fn get_schema_versions(&self, id: u64) -> Result<Vec<Schema>>;
+
+    fn get_cryptographic_key(&self, id: u64) -> Result<CryptographicKeys>;
+    fn get_cryptographic_keys(&self, filter: KeyFilter, page: Page) -> Result<Vec<CryptographicKeys>>;
+    fn create_cryptographic_key(&self, data: &CryptographicKeys) -> Result<u64>;
+    fn update_cryptographic_key(&self, data: &CryptographicKeys) -> Result<()>;
+    /// Issuers using the key are unlinked from it, refused while credentials reference the key
+    fn delete_cryptographic_key(&self, id: u64) -> Result<()>;
+
+    /// With its private key
+    fn get_issuer(&self, id: u64) -> Result<Issuer>;
+    /// Without their private keys
+    fn get_issuers(&self, page: Page) -> Result<Vec<Issuer>>;
+    fn create_issuer(&self, data: &Issuer) -> Result<u64>;
+    /// Only the public key reference changes, the private key is fixed at creation
+    fn update_issuer(&self, data: &Issuer) -> Result<()>;
+    fn delete_issuer(&self, id: u64) -> Result<()>;
+}
+
+/// Hands out Storage to requests, shared by every worker
+pub trait Backend: Send + Sync {
+    fn storage(&self) -> Result<Box<dyn Storage + '_>>;
+}
+
+/// Opens the configured backend, creating it when create is set, with the SQLite migrations it applied
+pub fn open(database: &DatabaseConfig, create: bool) -> std::result::Result<(Box<dyn Backend>, Vec<&'static Migration>), String> {
+    match database.backend {
+        BackendKind::Sqlite => {
+            let applied = database.migrate(create)?;
+            let pool = database.pool()
+                .map_err(|e| format!("database {} can not be opened: {}", database.path.display(), e))?;
+            Ok((Box::new(sqlite::SqliteBackend(pool)), applied))
+        }
+        BackendKind::Lmdb => Ok((Box::new(lmdb::LmdbBackend::open(database, create)?), vec![])),
+        BackendKind::Memory => Ok((Box::new(memory::MemoryBackend::default()), vec![])),
+    }
+}
@@ -0,0 +1,141 @@
+use std::ops::Deref;
+
+use r2d2::Pool;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::Connection;
+
+use crate::dao::{revision, unit_of_work, ConnectionRestMapping};
+use crate::datastructures::{Credential, CredentialFilter, CryptographicKeys, Issuer, KeyFilter, Page, Schema};
+
+use super::{Backend, Error, Resource, Result, Storage};
+
+pub struct SqliteBackend(pub Pool<SqliteConnectionManager>);
+
+impl Backend for SqliteBackend {
+    /// A connection from the pool, waiting for one to be returned when all are in use
+    fn storage(&self) -> Result<Box<dyn Storage + '_>> {
+        let conn = self.0.get().map_err(|e| Error::Backend(e.to_string()))?;
+        Ok(Box::new(SqliteStorage(conn)))
+    }
+}
+
+/// Storage over the dao on a pooled connection, or on the connection of a transaction, with foreign keys enforced by SQLite
+pub struct SqliteStorage<C>(C);
+
+impl<C: Deref<Target=Connection>> Storage for SqliteStorage<C> {
+    fn transaction(&self, f: &mut dyn FnMut(&dyn Storage) -> Result<()>) -> Result<()> {
+        unit_of_work(&self.0, |conn| f(&SqliteStorage(conn)))
+    }
+
+    fn revision(&self, resource: Resource, id: u64) -> Result<u64> {
+        Ok(revision(&self.0, resource.table(), id)?)
+    }
+
+    fn get_credential(&self, id: u64) -> Result<Credential> {
+        Credential::get_by_id(&self.0, id)?.ok_or(Error::NotFound)
+    }
+
+    fn get_credentials(&self, filter: CredentialFilter, page: Page) -> Result<Vec<Credential>> {
+        Ok(Credential::get_filtered(&self.0, filter, page)?)
+    }
+
+    fn create_credential(&self, data: &Credential) -> Result<u64> {
+        Ok(Credential::create(&self.0, data)?)
+    }
+
+    fn update_credential(&self, data: &Credential) -> Result<()> {
+        Ok(Credential::update(&self.0, data)?)
+    }
+
+    fn delete_credential(&self, id: u64) -> Result<()> {
+        Ok(Credential::delete_by_id(&self.0, id)?)
+    }
+
+    fn credentials_by_schema_id(&self, schema_id: u64) -> Result<Vec<Credential>> {
+        Ok(Credential::get_by_schema_id(&self.0, schema_id)?)
+    }
+
+    fn credential_ids_by_schema_id(&self, schema_id: u64) -> Result<Vec<u64>> {
+        Ok(Credential::ids_by_schema_id(&self.0, schema_id)?)
+    }
+
+    fn credential_ids_by_public_key_id(&self, public_key_id: u64) -> Result<Vec<u64>> {
+        Ok(Credential::ids_by_public_key_id(&self.0, public_key_id)?)
+    }
+
+    fn delete_credentials_by_schema_id(&self, schema_id: u64) -> Result<()> {
+        Ok(Credential::delete_by_schema_id(&self.0, schema_id)?)
+    }
+
+    fn delete_credentials_by_public_key_id(&self, public_key_id: u64) -> Result<()> {
+        Ok(Credential::delete_by_public_key_id(&self.0, public_key_id)?)
+    }
+
+    fn get_schema(&self, id: u64) -> Result<Schema> {
+        Schema::get_by_id(&self.0, id)?.ok_or(Error::NotFound)
+    }
+
+    fn get_schemas(&self, page: Page) -> Result<Vec<Schema>> {
+        Ok(Schema::get_all(&self.0, page)?)
+    }
+
+    fn create_schema(&self, data: &Schema) -> Result<u64> {
+        Ok(Schema::create(&self.0, data)?)
+    }
+
+    fn update_schema(&self, data: &Schema) -> Result<()> {
+        Ok(Schema::update(&self.0, data)?)
+    }
+
+    fn delete_schema(&self, id: u64) -> Result<()> {
+        Ok(Schema::delete_by_id(&self.0, id)?)
+    }
+
+    fn get_schema_version(&self, id: u64, version: u32) -> Result<Schema> {
+        Schema::get_version(&self.0, id, version)?.ok_or(Error::NotFound)
+    }
+
+    fn get_schema_versions(&self, id: u64) -> Result<Vec<Schema>> {
+        Ok(Schema::get_versions(&self.0, id)?)
+    }
+
+    fn get_cryptographic_key(&self, id: u64) -> Result<CryptographicKeys> {
+        CryptographicKeys::get_by_id(&self.0, id)?.ok_or(Error::NotFound)
+    }
+
+    fn get_cryptographic_keys(&self, filter: KeyFilter, page: Page) -> Result<Vec<CryptographicKeys>> {
+        Ok(CryptographicKeys::get_filtered(&self.0, filter, page)?)
+    }
+
+    fn create_cryptographic_key(&self, data: &CryptographicKeys) -> Result<u64> {
+        Ok(CryptographicKeys::create(&self.0, data)?)
+    }
+
+    fn update_cryptographic_key(&self, data: &CryptographicKeys) -> Result<()> {
+        Ok(CryptographicKeys::update(&self.0, data)?)
+    }
+
+    fn delete_cryptographic_key(&self, id: u64) -> Result<()> {
+        Ok(CryptographicKeys::delete_by_id(&self.0, id)?)
+    }
+
+    fn get_issuer(&self, id: u64) -> Result<Issuer> {
+        Issuer::get_by_id(&self.0, id)?.ok_or(Error::NotFound)
+    }
+
+    fn get_issuers(&self, page: Page) -> Result<Vec<Issuer>> {
+        Ok(Issuer::get_all(&self.0, page)?)
+    }
+
+    fn create_issuer(&self, data: &Issuer) -> Result<u64> {
+        Ok(Issuer::create(&self.0, data)?)
+    }
+
+    fn update_issuer(&self, data: &Issuer) -> Result<()> {
+        Ok(Issuer::update(&self.0, data)?)
+    }
+
+    fn delete_issuer(&self, id: u64) -> Result<()> {
+        Ok(Issuer::delete_by_id(&self.0, id)?)
+    }
+}
@@ -1,52 +1,277 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod test {
-    use super::super::get_ignited_rocket;
+    use super::super::setup_rocket;
+    use std::{env, fs, process};
+    use std::path::PathBuf;
+    use std::sync::OnceLock;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use rocket::config::{Config, ConfigBuilder, Environment};
+    use rocket::error::LaunchErrorKind;
     use rocket::local::Client;
-    use rocket::http::{ContentType, Status};
-    use crate::datastructures::Schema;
+    use rocket::http::{ContentType, Header, Status};
+    use rusqlite::{Connection, NO_PARAMS};
+    use crate::crypto::signing_input;
+    use crate::database::{BackendKind, DatabaseConfig};
+    use crate::storage;
+    use crate::datastructures::{Credential, CryptographicKeys, Issuer, Schema, SchemaValueType, SignatureAlgorithm};
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use ed25519_dalek::{Signer, SigningKey};
+
+
+    /// A freshly migrated database in the temp directory, shared by the tests of this run
+    fn test_database() -> String {
+        static DATABASE: OnceLock<PathBuf> = OnceLock::new();
+        DATABASE.get_or_init(|| {
+            let path = env::temp_dir().join(format!("rust-exercise-test-{}.sqlite", process::id()));
+            let _ = fs::remove_file(&path);
+            DatabaseConfig { path: path.clone(), ..DatabaseConfig::default() }.migrate(true).unwrap();
+            path
+        }).display().to_string()
+    }
+
+    fn config() -> ConfigBuilder {
+        Config::build(Environment::Development)
+            .extra("database_path", test_database())
+            .extra("issuer_master_key", BASE64.encode([9u8; 32]))
+    }
+
+    /// Rocket as main sets it up, on the test database instead of the one Rocket.toml names
+    fn get_rocket() -> rocket::Rocket {
+        setup_rocket(rocket::custom(config().finalize().unwrap()))
+    }
 
     #[test]
     fn rocket_simple() {
-        let client = Client::new(get_ignited_rocket()).expect("valid rocket instance");
+        Client::new(get_rocket()).expect("valid rocket instance");
     }
 
-    fn setup() {}
-
     fn get_client() -> Client {
-        let client = Client::new(get_ignited_rocket()).unwrap();
-        client
+        Client::new(get_rocket()).unwrap()
+    }
+
+    /// finger_print of the credential signed with the Ed25519 key of the seed
+    fn sign(seed: u8, cred: &Credential) -> Option<String> {
+        Some(BASE64.encode(SigningKey::from_bytes(&[seed; 32]).sign(signing_input(cred).as_bytes()).to_bytes()))
+    }
+
+    /// Stores the Ed25519 key of the seed
+    fn store_key(client: &Client, seed: u8) -> CryptographicKeys {
+        let public_key = BASE64.encode(SigningKey::from_bytes(&[seed; 32]).verifying_key().as_bytes());
+        let mut response = client.post("/cryptographic_keys").body(format!("{{\"public_key\": \"{}\"}}", public_key)).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    }
+
+    fn store_schema(client: &Client, schema: &str) -> Schema {
+        let mut response = client.post("/schemas").body(schema).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    }
+
+    /// Stores the key of the seed and the schema, and returns a credential of them with the data, pinned to version 1 and
+    /// signed but not stored
+    fn signed_credential(client: &Client, seed: u8, schema: &str, data: serde_json::Value) -> Credential {
+        let mut cred = Credential {
+            id: None,
+            schema_id: store_schema(client, schema).id,
+            schema_version: Some(1),
+            public_key_id: store_key(client, seed).id,
+            finger_print: None,
+            data: Some(data),
+        };
+        cred.finger_print = sign(seed, &cred);
+        cred
+    }
+
+    fn store_credential(client: &Client, cred: &Credential) -> Credential {
+        let mut response = client.post("/credentials").body(serde_json::to_string(cred).unwrap()).dispatch();
+        assert_eq!(response.status(), Status::Created);
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_database_config() {
+        let launches = |config: ConfigBuilder| match Client::new(setup_rocket(rocket::custom(config.finalize().unwrap()))) {
+            Ok(_) => true,
+            Err(e) => {
+                assert!(matches!(e.kind(), LaunchErrorKind::FailedFairings(_)));
+                false
+            }
+        };
+        assert!(launches(config().extra("database_journal_mode", "wal").extra("database_busy_timeout", 100)));
+        assert!(!launches(config().extra("database_path", "does-not-exist.sqlite")));
+        assert!(!launches(config().extra("database_journal_mode", "sometimes")));
+        assert!(!launches(config().extra("database_pool_size", 0)));
+        assert!(!launches(config().extra("database_read_only", "yes")));
+        assert!(!launches(config().extra("require_if_match", "no")));
+        assert!(!launches(config().extra("issuer_master_key", "not base64")));
+        assert!(!launches(config().extra("issuer_master_key", BASE64.encode([9u8; 16]))));
+        assert!(!launches(config().extra("issuer_master_key", 42)));
+
+        // An existing empty file is migrated, unless it is opened read only
+        let empty = env::temp_dir().join(format!("rust-exercise-test-{}-empty.sqlite", process::id()));
+        fs::write(&empty, b"").unwrap();
+        let empty_config = || config().extra("database_path", empty.display().to_string());
+        assert!(!launches(empty_config().extra("database_read_only", true)));
+        assert!(launches(empty_config()));
+        assert!(launches(empty_config().extra("database_read_only", true)));
+        // Migrated by a newer binary
+        Connection::open(&empty).unwrap()
+            .execute("INSERT INTO schema_migrations (version, name) VALUES (1000, '1000-future')", NO_PARAMS).unwrap();
+        assert!(!launches(empty_config()));
+        fs::remove_file(empty).unwrap();
+
+        let client = Client::new(setup_rocket(rocket::custom(config().extra("database_read_only", true).finalize().unwrap()))).unwrap();
+        let response = client.get("/schemas").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.post("/schemas").body("{\"schema\": {\"a\": \"Bool\"}}").dispatch();
+        assert_eq!(response.status(), Status::InternalServerError);
     }
 
     #[test]
-    fn test_create_schema() {
+    fn test_if_match_optional() {
+        let client = Client::new(setup_rocket(rocket::custom(config().extra("require_if_match", false).finalize().unwrap()))).unwrap();
+        let mut response = client.post("/schemas").body("{\"schema\": {\"a\": \"Bool\"}}").dispatch();
+        let id = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap().id.unwrap();
+        let response = client.put("/schemas").body(format!("{{\"id\": {}, \"schema\": {{\"a\": \"Int\"}}}}", id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client.delete(format!("/schemas/{}", id)).header(Header::new("If-Match", "\"1\"")).dispatch();
+        assert_eq!(response.status(), Status::PreconditionFailed);
+        let response = client.delete(format!("/schemas/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    /// Every backend runs the route tests of routes.rs
+    mod sqlite {
+        use super::*;
+
+        include!("routes.rs");
+    }
+
+    mod memory {
+        use super::*;
+
+        /// An empty store per client
+        fn get_client() -> Client {
+            Client::new(setup_rocket(rocket::custom(config().extra("database_backend", "memory").finalize().unwrap()))).unwrap()
+        }
+
+        include!("routes.rs");
+    }
+
+    mod lmdb {
+        use super::*;
+
+        /// An environment per client, a process must not open an environment twice
+        fn get_client() -> Client {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = env::temp_dir()
+                .join(format!("rust-exercise-test-{}-{}.lmdb", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+            let _ = fs::remove_dir_all(&path);
+            storage::open(&DatabaseConfig { path: path.clone(), backend: BackendKind::Lmdb, ..DatabaseConfig::default() }, true).unwrap();
+            let config = config().extra("database_backend", "lmdb").extra("database_path", path.display().to_string());
+            Client::new(setup_rocket(rocket::custom(config.finalize().unwrap()))).unwrap()
+        }
+
+        include!("routes.rs");
+    }
+
+    #[test]
+    fn test_64_bit_ids() {
         let client = get_client();
-        let body =
-            "{\"schema\": {
-    \"a\": \"Bool\",
-    \"b\": {
-      \"Map\": {
-        \"c\": \"Bool\"
-      }
-    },
-    \"d\": {
-      \"List\": [
-        {
-          \"Map\": {
-            \"e\": \"Float\"
-          }
-        },
-        \"Bool\"
-      ]
-    }
-  }
-}";
-        let body_json = serde_json::from_str::<Schema>(&body).unwrap();
-        let mut response = client.post("/schemas").body(body).dispatch();
-        assert_eq!(response.status(), Status::Created);
-        assert_eq!(response.content_type().expect("No content type"), ContentType::JSON);
-        let res = serde_json::from_str::<Schema>(&response.body().expect("No content body").into_string().unwrap()).expect("Can't decode json");
-        assert!(res.id.unwrap() >= 0);
-        assert!(res.id.unwrap() <= 2147483647);
-        assert_eq!(res.schema.unwrap(), body_json.schema.unwrap())
+        let cred = signed_credential(&client, 46, "{\"schema\": {\"a\": \"Bool\"}}", serde_json::json!({"a": true}));
+        let created = store_credential(&client, &cred).id.unwrap();
+
+        // Move the credential past u32::MAX, to an id that truncates to the one it was created with
+        let id = created + (1 << 32);
+        let conn = Connection::open(test_database()).unwrap();
+        conn.execute("UPDATE credentials SET id = ?1 WHERE id = ?2", [id as i64, created as i64]).unwrap();
+        let mut response = client.get(format!("/credentials/{}", id)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let stored = serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap();
+        assert_eq!((stored.id, stored.finger_print), (Some(id), cred.finger_print));
+        let response = client.get(format!("/credentials/{}", created)).dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let mut response = client.get("/credentials").dispatch();
+        assert!(response.body_string().unwrap().contains(&format!("\"id\":{}", id)));
+
+        // Ids SQLite can not store are simply not found
+        for path in ["/schemas/18446744073709551615", "/credentials/9223372036854775808", "/issuers/18446744073709551615"] {
+            let response = client.get(path).dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        }
+        let response = client.get("/schemas/18446744073709551616").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client.delete(format!("/credentials/{}", id)).header(Header::new("If-Match", "*")).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    /// GET /credentials with 100 stored, `cargo bench` compares opening a connection per request with the pool
+    mod bench {
+        extern crate test;
+
+        use std::{env, fs, process};
+
+        use rocket::local::Client;
+        use test::Bencher;
+
+        use crate::dao::ConnectionRestMapping;
+        use crate::database::DatabaseConfig;
+        use crate::datastructures::{Credential, CryptographicKeys, Page, Schema};
+
+        use super::config;
+
+        fn database(name: &str) -> DatabaseConfig {
+            let path = env::temp_dir().join(format!("rust-exercise-bench-{}-{}.sqlite", process::id(), name));
+            let _ = fs::remove_file(&path);
+            let database = DatabaseConfig { path, ..DatabaseConfig::default() };
+            database.migrate(true).unwrap();
+            let conn = database.open().unwrap();
+            let schema = serde_json::from_str::<Schema>("{\"schema\": {\"a\": \"Bool\"}}").unwrap();
+            let schema_id = Schema::create(&conn, &schema).unwrap();
+            let key = serde_json::from_str::<CryptographicKeys>("{\"public_key\": \"AAAA\"}").unwrap();
+            let public_key_id = CryptographicKeys::create(&conn, &key).unwrap();
+            for _ in 0..100 {
+                Credential::create(&conn, &Credential {
+                    id: None,
+                    schema_id: Some(schema_id),
+                    schema_version: None,
+                    public_key_id: Some(public_key_id),
+                    finger_print: Some("AAAA".to_owned()),
+                    data: Some(serde_json::json!({"a": true})),
+                }).unwrap();
+            }
+            database
+        }
+
+        #[bench]
+        fn get_credentials_new_connection(b: &mut Bencher) {
+            let database = database("new-connection");
+            b.iter(|| Credential::get_all(&database.open().unwrap(), Page::default()).unwrap());
+            fs::remove_file(database.path).unwrap();
+        }
+
+        #[bench]
+        fn get_credentials_pooled(b: &mut Bencher) {
+            let database = database("pooled");
+            let pool = database.pool().unwrap();
+            b.iter(|| Credential::get_all(&pool.get().unwrap(), Page::default()).unwrap());
+            drop(pool);
+            fs::remove_file(database.path).unwrap();
+        }
+
+        #[bench]
+        fn get_credentials_route(b: &mut Bencher) {
+            let database = database("route");
+            let rocket = crate::setup_rocket(rocket::custom(
+                config().extra("database_path", database.path.display().to_string()).finalize().unwrap()));
+            let client = Client::new(rocket).unwrap();
+            b.iter(|| client.get("/credentials").dispatch().body_string().unwrap());
+            drop(client);
+            fs::remove_file(database.path).unwrap();
+        }
     }
 }
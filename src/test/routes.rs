@@ -0,0 +1,634 @@
+// The route tests every backend runs, included by the backend modules of mod.rs with their own get_client
+
+/// If-Match with the ETag the object at path has now
+fn if_match(client: &Client, path: &str) -> Header<'static> {
+    let response = client.get(path).dispatch();
+    Header::new("If-Match", response.headers().get_one("ETag").expect("No ETag").to_owned())
+}
+
+#[test]
+#[allow(unused_comparisons, clippy::absurd_extreme_comparisons)] // Kept from when ids were signed
+fn test_create_schema() {
+    let client = get_client();
+    let body =
+        "{\"schema\": {
+    \"a\": \"Bool\",
+    \"b\": {
+      \"Map\": {
+        \"c\": \"Bool\"
+      }
+    },
+    \"d\": {
+      \"List\": [
+        {
+          \"Map\": {
+            \"e\": \"Float\"
+          }
+        },
+        \"Bool\"
+      ]
+    }
+  }
+}";
+    let body_json = serde_json::from_str::<Schema>(body).unwrap();
+    let mut response = client.post("/schemas").body(body).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    assert_eq!(response.content_type().expect("No content type"), ContentType::JSON);
+    let res = serde_json::from_str::<Schema>(&response.body().expect("No content body").into_string().unwrap()).expect("Can't decode json");
+    assert!(res.id.unwrap() >= 0);
+    assert!(res.id.unwrap() <= 2147483647);
+    assert_eq!(res.schema.unwrap(), body_json.schema.unwrap())
+}
+
+#[test]
+fn test_create_schema_constraints() {
+    let client = get_client();
+    let response = client.post("/schemas")
+        .body("{\"schema\": {\"a\": {\"Constrained\": {\"type\": \"String\", \"pattern\": \"[\"}}}}")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let mut response = client.post("/schemas")
+        .body("{\"schema\": {\"a\": {\"Constrained\": {\"type\": \"Int\", \"minimum\": 0}}}}")
+        .dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let schema = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    let mut response = client.post("/credentials")
+        .body(format!("{{\"schema_id\": {}, \"public_key_id\": 1, \"data\": {{\"a\": -1}}}}", schema.id.unwrap()))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(body["error"], "Invalid or Nonconforming schema");
+    assert_eq!(body["details"][0]["message"], "-1 is less than the minimum 0");
+}
+
+#[test]
+fn test_json_schema() {
+    let client = get_client();
+    let document = "{\"type\": \"object\", \"properties\": {\"birth_date\": {\"type\": \"string\", \"format\": \"date\"}},
+                     \"required\": [\"birth_date\"], \"additionalProperties\": false}";
+    let mut response = client.post("/schemas/import/jsonschema").body(document).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let schema = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((schema.additional_properties, schema.version), (Some(false), Some(1)));
+
+    let mut response = client.get(format!("/schemas/{}/jsonschema", schema.id.unwrap())).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let exported = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(exported["properties"]["birth_date"]["format"], "date");
+    assert_eq!(exported["required"][0], "birth_date");
+
+    let mut response = client.post("/schemas/import/jsonschema")
+        .body("{\"type\": \"object\", \"properties\": {\"a\": {\"$ref\": \"#/$defs/a\"}}}")
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(body["details"]["path"], "/properties/a/$ref");
+}
+
+#[test]
+fn test_create_credential_signature() {
+    let client = get_client();
+    let mut cred = signed_credential(&client, 42, "{\"schema\": {\"a\": \"Bool\"}}", serde_json::json!({"a": true}));
+    cred.finger_print = Some(BASE64.encode([0u8; 64]));
+    let response = client.post("/credentials").body(serde_json::to_string(&cred).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    cred.data = Some(serde_json::json!({"a": "true"}));
+    let mut response = client.post("/credentials").body(serde_json::to_string(&cred).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(body["details"], serde_json::json!([
+        {"path": "/a", "expected": "Bool", "actual": "string", "message": "type mismatch"}
+    ]));
+
+    cred.data = Some(serde_json::json!({"a": true}));
+    cred.finger_print = sign(42, &cred);
+    let res = store_credential(&client, &cred);
+    assert_eq!(res.finger_print, cred.finger_print);
+
+    let mut response = client.get(format!("/credentials/{}/signing_input", res.id.unwrap())).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(body["signing_input"], signing_input(&cred));
+}
+
+#[test]
+fn test_schema_versions() {
+    let client = get_client();
+    let key = store_key(&client, 43);
+    let schema = store_schema(&client, "{\"name\": \"person\", \"schema\": {\"a\": \"Bool\"}}");
+    assert_eq!(schema.version, Some(1));
+    let id = schema.id.unwrap();
+
+    // No credentials yet, the update still publishes version 2 and leaves version 1 as it was
+    let mut response = client.get(format!("/schemas/{}/versions/1", id)).dispatch();
+    let version_1 = response.body_string().unwrap();
+    let mut response = client.put("/schemas")
+        .header(if_match(&client, &format!("/schemas/{}", id)))
+        .body(format!("{{\"id\": {}, \"schema\": {{\"a\": \"Int\"}}}}", id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let schema = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((schema.version, schema.name), (Some(2), Some("person".to_owned())));
+    let mut response = client.get(format!("/schemas/{}/versions/1", id)).dispatch();
+    assert_eq!(response.body_string().unwrap(), version_1);
+
+    let mut cred = Credential {
+        id: None,
+        schema_id: Some(id),
+        schema_version: None,
+        public_key_id: key.id,
+        finger_print: None,
+        data: Some(serde_json::json!({"a": 1})),
+    };
+    // Not pinned, it is signed over the latest version it gets pinned to
+    cred.finger_print = sign(43, &Credential { schema_version: Some(2), ..cred.clone() });
+    let stored = store_credential(&client, &cred);
+    assert_eq!(stored.schema_version, Some(2));
+
+    // Version 2 is pinned, the update publishes version 3
+    let mut response = client.put("/schemas")
+        .header(if_match(&client, &format!("/schemas/{}", id)))
+        .body(format!("{{\"id\": {}, \"schema\": {{\"a\": \"String\"}}}}", id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let schema = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(schema.version, Some(3));
+
+    let mut response = client.get(format!("/schemas/{}/versions", id)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let versions = serde_json::from_str::<Vec<Schema>>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(versions.iter().map(|s| s.version.unwrap()).collect::<Vec<_>>(), vec!(1, 2, 3));
+    assert_eq!(versions[0].schema.as_ref().unwrap()["a"], SchemaValueType::Bool);
+    assert_eq!(versions[1].schema.as_ref().unwrap()["a"], SchemaValueType::Int);
+
+    // The stored credential still verifies against its pinned version, but no longer conforms to the latest
+    let mut response = client.post("/verify").body(serde_json::to_string(&stored).unwrap()).dispatch();
+    let report = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((report["valid"].as_bool(), report["schema_version"].as_u64()), (Some(true), Some(2)));
+    let response = client.post("/credentials").body(serde_json::to_string(&cred).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    assert_eq!(client.get(format!("/schemas/{}/versions/4", id)).dispatch().status(), Status::NotFound);
+}
+
+#[test]
+fn test_schema_compatibility() {
+    let client = get_client();
+    let mut cred = signed_credential(&client, 44, "{\"schema\": {\"a\": \"Int\"}}", serde_json::json!({"a": -1}));
+    let id = cred.schema_id.unwrap();
+    let mut ids = vec!();
+    for a in [-1, 1].iter() {
+        cred.data = Some(serde_json::json!({"a": a}));
+        cred.finger_print = sign(44, &cred);
+        ids.push(store_credential(&client, &cred).id.unwrap());
+    }
+
+    let mut response = client.post(format!("/schemas/{}/compatibility", id))
+        .body("{\"schema\": {\"a\": {\"Constrained\": {\"type\": \"Int\", \"minimum\": 0}}, \"b\": \"Bool\"}}")
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let report = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(report["compatibility"], "Forward");
+    assert_eq!(report["changes"], serde_json::json!([
+        {"path": "/a", "change": "Narrowed", "backward": false, "forward": true},
+        {"path": "/b", "change": "AddedOptional", "backward": true, "forward": true},
+    ]));
+    assert_eq!(report["credentials_checked"], 2);
+    assert_eq!(report["breaking_credential_ids"], serde_json::json!([ids[0]]));
+
+    let response = client.post("/schemas/4294967295/compatibility").body("{\"schema\": {}}").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+}
+
+#[test]
+fn test_delete_referenced() {
+    let client = get_client();
+    let cred = signed_credential(&client, 45, "{\"schema\": {\"a\": \"Bool\"}}", serde_json::json!({"a": true}));
+    let cred_id = store_credential(&client, &cred).id.unwrap();
+
+    for path in [format!("/schemas/{}", cred.schema_id.unwrap()), format!("/cryptographic_keys/{}", cred.public_key_id.unwrap())].iter() {
+        let mut response = client.delete(path.as_str()).header(if_match(&client, path)).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+        let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+        assert_eq!(body["details"]["credential_ids"], serde_json::json!([cred_id]));
+        let response = client.delete(format!("{}?on_delete=restrict", path)).header(if_match(&client, path)).dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+    }
+    let response = client.delete(format!("/schemas/{}?on_delete=drop", cred.schema_id.unwrap())).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let response = client.delete(format!("/schemas/{}?on_delete=cascade", cred.schema_id.unwrap()))
+        .header(if_match(&client, &format!("/schemas/{}", cred.schema_id.unwrap())))
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(client.get(format!("/credentials/{}", cred_id)).dispatch().status(), Status::NotFound);
+    assert_eq!(client.get(format!("/schemas/{}", cred.schema_id.unwrap())).dispatch().status(), Status::NotFound);
+    let path = format!("/cryptographic_keys/{}", cred.public_key_id.unwrap());
+    let response = client.delete(path.as_str()).header(if_match(&client, &path)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+}
+
+#[test]
+fn test_create_cryptographic_key_algorithm() {
+    let client = get_client();
+    let signing_key = SigningKey::from_bytes(&[42u8; 32]);
+    let public_key = BASE64.encode(signing_key.verifying_key().as_bytes());
+
+    let body = format!("{{\"public_key\": \"{}\", \"algorithm\": \"EcdsaP256\"}}", public_key);
+    let response = client.post("/cryptographic_keys").body(body).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    let body = format!("{{\"public_key\": \"{}\"}}", public_key);
+    let mut response = client.post("/cryptographic_keys").body(body).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let key = serde_json::from_str::<CryptographicKeys>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(key.algorithm, Some(SignatureAlgorithm::Ed25519));
+}
+
+#[test]
+fn test_issue_credential() {
+    let client = get_client();
+    let mut response = client.post("/schemas").body("{\"schema\": {\"a\": \"Bool\"}}").dispatch();
+    let schema = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    let mut response = client.post("/issuers").body("{\"algorithm\": \"EcdsaP256\"}").dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let issuer = serde_json::from_str::<Issuer>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(issuer.algorithm, Some(SignatureAlgorithm::EcdsaP256));
+
+    let body = format!("{{\"schema_id\": {}, \"issuer_id\": {}, \"data\": {{\"a\": 1}}}}", schema.id.unwrap(), issuer.id.unwrap());
+    let response = client.post("/credentials/issue").body(body).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    // The rejected issuance stored no key and left the issuer as it was
+    let mut response = client.get(format!("/issuers/{}", issuer.id.unwrap())).dispatch();
+    assert_eq!(response.headers().get_one("ETag"), Some("\"1\""));
+    assert_eq!(serde_json::from_str::<Issuer>(&response.body_string().unwrap()).unwrap().public_key_id, None);
+
+    let body = format!("{{\"schema_id\": {}, \"issuer_id\": {}, \"data\": {{\"a\": true}}}}", schema.id.unwrap(), issuer.id.unwrap());
+    let mut response = client.post("/credentials/issue").body(body.clone()).dispatch();
+    assert_eq!(response.status(), Status::Created);
+    let first = serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap();
+    assert!(first.finger_print.is_some());
+    let mut response = client.post("/credentials/issue").body(body).dispatch();
+    let second = serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(first.public_key_id, second.public_key_id);
+
+    let mut response = client.get(format!("/issuers/{}", issuer.id.unwrap())).dispatch();
+    let body = response.body_string().unwrap();
+    assert!(!body.contains("private_key"));
+    assert_eq!(serde_json::from_str::<Issuer>(&body).unwrap().public_key_id, first.public_key_id);
+    let mut response = client.get(format!("/cryptographic_keys/{}", first.public_key_id.unwrap())).dispatch();
+    let key = serde_json::from_str::<CryptographicKeys>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(key.algorithm, Some(SignatureAlgorithm::EcdsaP256));
+}
+
+#[test]
+fn test_verify_credential() {
+    let client = get_client();
+    let cred = signed_credential(&client, 43, "{\"schema\": {\"a\": \"Bool\"}}", serde_json::json!({"a": true}));
+
+    let verify = |body: String| -> serde_json::Value {
+        let mut response = client.post("/verify").body(body).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str(&response.body_string().unwrap()).unwrap()
+    };
+    let report = verify(serde_json::to_string(&cred).unwrap());
+    assert_eq!(report["valid"], true);
+    assert_eq!(report["key_inline"], false);
+
+    // Nothing is stored by verification
+    let mut response = client.get("/credentials").dispatch();
+    assert!(!response.body_string().unwrap().contains(cred.finger_print.as_ref().unwrap()));
+
+    let mut bad = cred.clone();
+    bad.data = Some(serde_json::json!({"a": 1}));
+    let report = verify(serde_json::to_string(&bad).unwrap());
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["schema_conforms"], false);
+    assert_eq!(report["signature_valid"], false);
+    assert_eq!(report["errors"], serde_json::json!(["Invalid or Nonconforming schema", "finger_print does not match credential data"]));
+
+    // Inline key takes precedence over the stored one
+    let other_key = BASE64.encode(SigningKey::from_bytes(&[44u8; 32]).verifying_key().as_bytes());
+    let mut body = serde_json::to_value(&cred).unwrap();
+    body["public_key"] = serde_json::json!({"public_key": other_key});
+    let report = verify(body.to_string());
+    assert_eq!((report["key_inline"].as_bool(), report["key_found"].as_bool()), (Some(true), Some(false)));
+    assert_eq!(report["signature_valid"], false);
+
+    // Revocation and expiry of an inline key are ignored
+    let public_key = BASE64.encode(SigningKey::from_bytes(&[43u8; 32]).verifying_key().as_bytes());
+    body["public_key"] = serde_json::json!({"public_key": public_key, "revoked": true, "expires_at": "2000-01-01T00:00:00Z"});
+    let report = verify(body.to_string());
+    assert_eq!((report["valid"].as_bool(), report["key_revoked"].as_bool()), (Some(true), Some(false)));
+    assert_eq!((report["key_expired"].as_bool(), report["key_expires_at"].is_null()), (Some(false), true));
+
+    let key_path = format!("/cryptographic_keys/{}", cred.public_key_id.unwrap());
+    let mut response = client.get(key_path.as_str()).dispatch();
+    let mut key = serde_json::from_str::<CryptographicKeys>(&response.body_string().unwrap()).unwrap();
+    key.revoked = Some(true);
+    let response = client.put("/cryptographic_keys").header(if_match(&client, &key_path)).body(serde_json::to_string(&key).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let report = verify(serde_json::to_string(&cred).unwrap());
+    assert_eq!(report["valid"], false);
+    assert_eq!(report["key_revoked"], true);
+    assert_eq!(report["signature_valid"], true);
+    let response = client.post("/credentials").body(serde_json::to_string(&cred).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    key.revoked = Some(false);
+    key.expires_at = Some("2000-01-01T00:00:00Z".parse().unwrap());
+    client.put("/cryptographic_keys").header(if_match(&client, &key_path)).body(serde_json::to_string(&key).unwrap()).dispatch();
+    let report = verify(serde_json::to_string(&cred).unwrap());
+    assert_eq!(report["key_expired"], true);
+    assert_eq!(report["key_expires_at"], "2000-01-01T00:00:00Z");
+    assert_eq!(report["errors"], serde_json::json!(["Key has expired"]));
+}
+
+#[test]
+fn test_if_match() {
+    let client = get_client();
+    let mut response = client.post("/schemas").body("{\"schema\": {\"a\": \"Bool\"}}").dispatch();
+    let id = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap().id.unwrap();
+    let path = format!("/schemas/{}", id);
+    let update = |etag: Option<&str>, schema: &str| {
+        let mut request = client.put("/schemas").body(format!("{{\"id\": {}, \"schema\": {{\"a\": \"{}\"}}}}", id, schema));
+        if let Some(etag) = etag {
+            request = request.header(Header::new("If-Match", etag.to_owned()));
+        }
+        request.dispatch()
+    };
+    let etag = client.get(path.as_str()).dispatch().headers().get_one("ETag").unwrap().to_owned();
+    assert_eq!(etag, "\"1\"");
+
+    assert_eq!(update(None, "Int").status(), Status::PreconditionRequired);
+    // Two admins read the same ETag, the second update is refused rather than overwriting the first
+    let response = update(Some(&etag), "Int");
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+    let response = update(Some(&etag), "String");
+    assert_eq!(response.status(), Status::PreconditionFailed);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+    let mut response = client.get(path.as_str()).dispatch();
+    assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+    assert_eq!(serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap().schema.unwrap()["a"], SchemaValueType::Int);
+    assert_eq!(update(Some("\"1\", \"2\""), "String").status(), Status::Ok);
+    assert_eq!(update(Some("W/\"3\""), "Bool").status(), Status::PreconditionFailed);
+
+    assert_eq!(client.delete(path.as_str()).dispatch().status(), Status::PreconditionRequired);
+    let response = client.delete(path.as_str()).header(Header::new("If-Match", "\"2\"")).dispatch();
+    assert_eq!(response.status(), Status::PreconditionFailed);
+    let response = client.delete(path.as_str()).header(Header::new("If-Match", "*")).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let response = client.delete(path.as_str()).header(Header::new("If-Match", "*")).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(update(Some("*"), "Int").status(), Status::NotFound);
+}
+
+#[test]
+fn test_put_and_patch() {
+    let client = get_client();
+    let mut cred = signed_credential(&client, 47, "{\"name\": \"flag\", \"schema\": {\"a\": \"Bool\"}}", serde_json::json!({"a": true}));
+    let id = store_credential(&client, &cred).id.unwrap();
+    let path = format!("/credentials/{}", id);
+    let merge_patch = ContentType::new("application", "merge-patch+json");
+    let json_patch = ContentType::new("application", "json-patch+json");
+
+    // PUT replaces data and finger_print, the rest may be left out but not changed
+    cred.data = Some(serde_json::json!({"a": false}));
+    cred.finger_print = sign(47, &cred);
+    let body = serde_json::json!({"data": cred.data, "finger_print": cred.finger_print});
+    let mut response = client.put(path.as_str()).header(if_match(&client, &path)).body(body.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(response.headers().get_one("ETag"), Some("\"2\""));
+    let stored = serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((stored.id, stored.schema_version, stored.data), (Some(id), Some(1), cred.data.clone()));
+    let response = client.put(path.as_str()).header(if_match(&client, &path))
+        .body(serde_json::json!({"id": id + 1, "data": cred.data, "finger_print": cred.finger_print}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = client.put(path.as_str()).header(if_match(&client, &path))
+        .body(serde_json::json!({"schema_id": id + 1000, "data": cred.data, "finger_print": cred.finger_print}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let response = client.put(path.as_str()).header(if_match(&client, &path))
+        .body(serde_json::json!({"data": {"a": true}, "finger_print": cred.finger_print}).to_string()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // Merge patch, the patched data is re-validated and must be signed
+    let response = client.patch(path.as_str()).header(if_match(&client, &path)).header(merge_patch.clone())
+        .body("{\"data\": {\"a\": 1}}").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    cred.data = Some(serde_json::json!({"a": true}));
+    cred.finger_print = sign(47, &cred);
+    let body = serde_json::json!({"data": {"a": true}, "finger_print": cred.finger_print});
+    let mut response = client.patch(path.as_str()).header(if_match(&client, &path)).header(merge_patch.clone())
+        .body(body.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap().data, cred.data);
+
+    // JSON Patch, a failed test operation changes nothing
+    cred.data = Some(serde_json::json!({"a": false}));
+    cred.finger_print = sign(47, &cred);
+    let patch = serde_json::json!([
+        {"op": "test", "path": "/data/a", "value": false},
+        {"op": "replace", "path": "/data/a", "value": false},
+        {"op": "replace", "path": "/finger_print", "value": cred.finger_print},
+    ]);
+    let response = client.patch(path.as_str()).header(if_match(&client, &path)).header(json_patch.clone())
+        .body(patch.to_string()).dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    let patch = serde_json::json!([
+        {"op": "test", "path": "/data/a", "value": true},
+        {"op": "replace", "path": "/data/a", "value": false},
+        {"op": "replace", "path": "/finger_print", "value": cred.finger_print},
+    ]);
+    let response = client.patch(path.as_str()).header(if_match(&client, &path)).header(json_patch.clone())
+        .body(patch.to_string()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let mut response = client.get(path.as_str()).dispatch();
+    assert_eq!(response.headers().get_one("ETag"), Some("\"4\""));
+    assert_eq!(serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap().data, cred.data);
+
+    let response = client.patch(path.as_str()).header(if_match(&client, &path)).header(ContentType::JSON).body("{}").dispatch();
+    assert_eq!(response.status(), Status::UnsupportedMediaType);
+    let response = client.patch(path.as_str()).header(merge_patch.clone()).body("{}").dispatch();
+    assert_eq!(response.status(), Status::PreconditionRequired);
+    let response = client.put(format!("/credentials/{}", i64::MAX)).header(Header::new("If-Match", "*")).body("{}").dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+
+    // Schemas keep their name and publish a new version on every change
+    let schema_path = format!("/schemas/{}", cred.schema_id.unwrap());
+    let mut response = client.put(schema_path.as_str()).header(if_match(&client, &schema_path))
+        .body("{\"schema\": {\"a\": \"Bool\", \"b\": \"Int\"}}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let stored = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((stored.name.as_deref(), stored.version), (Some("flag"), Some(2)));
+    let mut response = client.patch(schema_path.as_str()).header(if_match(&client, &schema_path)).header(merge_patch.clone())
+        .body("{\"name\": \"renamed\", \"schema\": {\"b\": null}}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let stored = serde_json::from_str::<Schema>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((stored.name.as_deref(), stored.version), (Some("renamed"), Some(3)));
+    assert!(!stored.schema.unwrap().contains_key("b"));
+    let response = client.patch(schema_path.as_str()).header(if_match(&client, &schema_path)).header(json_patch.clone())
+        .body("[{\"op\": \"remove\", \"path\": \"/schema\"}]").dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+
+    // Keys
+    let key_path = format!("/cryptographic_keys/{}", cred.public_key_id.unwrap());
+    let public_key = BASE64.encode(SigningKey::from_bytes(&[47u8; 32]).verifying_key().as_bytes());
+    let mut response = client.patch(key_path.as_str()).header(if_match(&client, &key_path)).header(merge_patch.clone())
+        .body("{\"revoked\": true}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let stored = serde_json::from_str::<CryptographicKeys>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((stored.revoked, stored.public_key), (Some(true), Some(public_key)));
+    let response = client.patch(key_path.as_str()).header(if_match(&client, &key_path)).header(json_patch)
+        .body("[{\"op\": \"replace\", \"path\": \"/public_key\", \"value\": \"AAAA\"}]").dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    // The credential verifies with the key, so its public_key can not change, it can once nothing references the key
+    let other_key = BASE64.encode(SigningKey::from_bytes(&[51u8; 32]).verifying_key().as_bytes());
+    let replace_key = format!("{{\"public_key\": \"{}\"}}", other_key);
+    let mut response = client.patch(key_path.as_str()).header(if_match(&client, &key_path)).header(merge_patch.clone())
+        .body(replace_key.clone()).dispatch();
+    assert_eq!(response.status(), Status::Conflict);
+    let body = serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(body["details"]["credential_ids"], serde_json::json!([id]));
+    let response = client.delete(path.as_str()).header(if_match(&client, &path)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let mut response = client.patch(key_path.as_str()).header(if_match(&client, &key_path)).header(merge_patch)
+        .body(replace_key).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let stored = serde_json::from_str::<CryptographicKeys>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(stored.public_key, Some(other_key));
+}
+
+#[test]
+fn test_update_credential() {
+    let client = get_client();
+    let mut cred = signed_credential(&client, 48, "{\"schema\": {\"a\": \"Bool\"}}", serde_json::json!({"a": true}));
+    let created = store_credential(&client, &cred);
+    let path = format!("/credentials/{}", created.id.unwrap());
+    let of_schema = format!("/credentials?schema_id={}", cred.schema_id.unwrap());
+
+    // The credential is updated in place, not stored again
+    cred.id = created.id;
+    cred.data = Some(serde_json::json!({"a": false}));
+    cred.finger_print = sign(48, &cred);
+    let mut response = client.put("/credentials").header(if_match(&client, &path)).body(serde_json::to_string(&cred).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let updated = serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap();
+    assert_eq!((updated.id, updated.data, updated.finger_print), (created.id, cred.data.clone(), cred.finger_print.clone()));
+    let mut response = client.get(of_schema).dispatch();
+    let stored = serde_json::from_str::<Vec<Credential>>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(stored.iter().map(|c| c.id).collect::<Vec<_>>(), [created.id]);
+    let mut response = client.get(path.as_str()).dispatch();
+    assert_eq!(serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap().data, cred.data);
+
+    // Schema and signature are checked again
+    let mut unsigned = cred.clone();
+    unsigned.data = Some(serde_json::json!({"a": true}));
+    let response = client.put("/credentials").header(if_match(&client, &path)).body(serde_json::to_string(&unsigned).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let mut nonconforming = cred.clone();
+    nonconforming.data = Some(serde_json::json!({"a": "false"}));
+    nonconforming.finger_print = sign(48, &nonconforming);
+    let response = client.put("/credentials").header(if_match(&client, &path)).body(serde_json::to_string(&nonconforming).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    let mut response = client.get(path.as_str()).dispatch();
+    assert_eq!(serde_json::from_str::<Credential>(&response.body_string().unwrap()).unwrap().data, cred.data);
+
+    let mut unknown = cred.clone();
+    unknown.id = Some(i64::MAX as u64);
+    let response = client.put("/credentials").header(Header::new("If-Match", "*")).body(serde_json::to_string(&unknown).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    cred.id = None;
+    let response = client.put("/credentials").header(Header::new("If-Match", "*")).body(serde_json::to_string(&cred).unwrap()).dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+}
+
+#[test]
+fn test_list_filters() {
+    let client = get_client();
+    let ids = |path: &str| {
+        let mut response = client.get(path).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str::<Vec<serde_json::Value>>(&response.body_string().unwrap()).unwrap()
+            .iter().map(|o| o["id"].as_u64().unwrap()).collect::<Vec<_>>()
+    };
+    let seeds = [49, 50];
+    let key_ids = seeds.map(|seed| store_key(&client, seed).id.unwrap());
+    let schema_ids = [0; 2].map(|_| store_schema(&client, "{\"schema\": {\"a\": \"Bool\"}}").id.unwrap());
+    let mut credential_ids = vec![];
+    for (schema, key) in [(0, 0), (0, 1), (1, 0)] {
+        let mut cred = Credential {
+            id: None,
+            schema_id: Some(schema_ids[schema]),
+            schema_version: Some(1),
+            public_key_id: Some(key_ids[key]),
+            finger_print: None,
+            data: Some(serde_json::json!({"a": true})),
+        };
+        cred.finger_print = sign(seeds[key], &cred);
+        credential_ids.push(store_credential(&client, &cred).id.unwrap());
+    }
+
+    let by_schema = format!("/credentials?schema_id={}", schema_ids[0]);
+    assert_eq!(ids(&by_schema), credential_ids[..2].to_vec());
+    assert_eq!(ids(&format!("{}&public_key_id={}", by_schema, key_ids[1])), vec![credential_ids[1]]);
+    assert_eq!(ids(&format!("/credentials?public_key_id={}", key_ids[0])), vec![credential_ids[0], credential_ids[2]]);
+    assert_eq!(ids(&format!("{}&sort=-id", by_schema)), vec![credential_ids[1], credential_ids[0]]);
+    assert_eq!(ids(&format!("{}&sort=-id&limit=1&offset=1", by_schema)), vec![credential_ids[0]]);
+    assert_eq!(ids(&format!("{}&sort=id", by_schema)), credential_ids[..2].to_vec());
+    let mut response = client.get(format!("{}&fields=id,data&sort=-id", by_schema)).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(serde_json::from_str::<serde_json::Value>(&response.body_string().unwrap()).unwrap(), serde_json::json!([
+        {"id": credential_ids[1], "data": {"a": true}},
+        {"id": credential_ids[0], "data": {"a": true}},
+    ]));
+    assert_eq!(client.get(format!("{}&sort=name", by_schema)).dispatch().status(), Status::BadRequest);
+
+    let schemas = ids("/schemas?sort=-id");
+    assert!(schemas.windows(2).all(|w| w[0] > w[1]));
+    assert!(schemas.iter().position(|&id| id == schema_ids[1]) < schemas.iter().position(|&id| id == schema_ids[0]));
+    let mut response = client.get("/schemas?fields=version,id,unknown&limit=1").dispatch();
+    let schemas = serde_json::from_str::<Vec<serde_json::Map<String, serde_json::Value>>>(&response.body_string().unwrap()).unwrap();
+    assert_eq!(schemas[0].keys().collect::<Vec<_>>(), ["id", "version"]);
+
+    let key_path = format!("/cryptographic_keys/{}", key_ids[1]);
+    let response = client.patch(key_path.as_str()).header(if_match(&client, &key_path))
+        .header(ContentType::new("application", "merge-patch+json")).body("{\"revoked\": true}").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let revoked = ids("/cryptographic_keys?revoked=true&fields=id");
+    assert!(revoked.contains(&key_ids[1]) && !revoked.contains(&key_ids[0]));
+    let ed25519 = ids("/cryptographic_keys?algorithm=Ed25519&revoked=false&sort=-id");
+    assert!(ed25519.contains(&key_ids[0]) && !ed25519.contains(&key_ids[1]));
+    assert!(ids("/cryptographic_keys?algorithm=EcdsaP256").iter().all(|id| !key_ids.contains(id)));
+    assert_eq!(client.get("/cryptographic_keys?algorithm=Unknown").dispatch().status(), Status::BadRequest);
+}
+
+#[test]
+fn test_pagination() {
+    let client = get_client();
+    for _ in 0..3 {
+        client.post("/schemas").body("{\"schema\": {\"a\": \"Bool\"}}").dispatch();
+    }
+    let mut response = client.get("/schemas?limit=2").dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    assert_eq!(serde_json::from_str::<Vec<Schema>>(&response.body_string().unwrap()).unwrap().len(), 2);
+
+    // Other tests write to the same tables, so the pages checked are of this test's own credentials
+    let mut cred = signed_credential(&client, 52, "{\"schema\": {\"a\": \"Int\"}}", serde_json::json!({"a": 0}));
+    let mut own = vec![];
+    for a in 0..3 {
+        cred.data = Some(serde_json::json!({"a": a}));
+        cred.finger_print = sign(52, &cred);
+        own.push(store_credential(&client, &cred).id.unwrap());
+    }
+    let page = |query: &str| {
+        let mut response = client.get(format!("/credentials?schema_id={}&{}", cred.schema_id.unwrap(), query)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        serde_json::from_str::<Vec<Credential>>(&response.body_string().unwrap()).unwrap()
+            .iter().map(|c| c.id.unwrap()).collect::<Vec<_>>()
+    };
+    assert_eq!(page("limit=2"), own[..2].to_vec());
+    assert_eq!(page("offset=1"), own[1..].to_vec());
+    assert_eq!(page("offset=1&limit=1"), vec![own[1]]);
+    assert!(page("offset=3").is_empty());
+}